@@ -0,0 +1,175 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use libc::pid_t;
+
+use super::common::{uk64, Arch};
+use super::core::default_lib_paths;
+use super::elf::Elf;
+use super::proc::Proc;
+use super::profile::LibcProfile;
+use super::ptrace;
+use super::resolve;
+use super::structs::{malloc_par_generator, malloc_state_generator};
+
+const SIZE_T: u64 = 8;
+const TCACHE_MARKER: u64 = 0xdeadbeef;
+const M_MXFAST: u64 = 1;
+const M_TRIM_THRESHOLD: u64 = (-1i64) as u64;
+const MXFAST_MARKER: u64 = 0x50;
+const TRIM_MARKER: u64 = 0x31337000;
+
+/// Runs `libc_path` as a program under `ld_path`, stops it at its entry
+/// point and probes malloc through remote calls, the way `libc_info.c` did
+/// from inside the process.
+pub fn calibrate(libc_path: &str, ld_path: Option<&str>) -> io::Result<LibcProfile> {
+    let libc = fs::canonicalize(libc_path)?.to_string_lossy().to_string();
+    let ld = match ld_path {
+        Some(p) => fs::canonicalize(p)?.to_string_lossy().to_string(),
+        None => default_lib_paths(Arch::X64).1.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No ld.so found, pass --ld")
+        })?,
+    };
+    let libc_dir = Path::new(&libc)
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| ".".to_string());
+    let elf = Elf::open(&libc)?;
+    if elf.arch() != Arch::X64 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "calibrate can only run x86_64 libcs; for a 32-bit libc run libc/libc_info32 \
+             under it and load the output with `profile add`",
+        ));
+    }
+
+    let pid = ptrace::spawn_traced(&[&ld, "--library-path", &libc_dir, &libc])?;
+    let result = probe(pid, &libc, &elf);
+    ptrace::kill(pid);
+    result
+}
+
+fn probe(pid: pid_t, libc: &str, elf: &Elf) -> io::Result<LibcProfile> {
+    let proc = Proc::new(pid as u64);
+    let libc_base = wait_for_libc(pid, &proc, libc)?;
+
+    let entry = libc_base + elf.entry();
+    ptrace::set_breakpoint(pid, entry)?;
+    ptrace::cont(pid, 0)?;
+    if ptrace::wait_stop(pid)? != Some(libc::SIGTRAP) {
+        return Err(io::Error::other("Probe did not reach the libc entry point"));
+    }
+
+    let libc_version = resolve::libc_version(&proc, Some(libc))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No glibc version banner"))?;
+    let malloc = libc_base + symbol(elf, "malloc")?;
+    let free = libc_base + symbol(elf, "free")?;
+    // Every call returns onto the entry breakpoint.
+    let call = |func: u64, arg: u64| ptrace::remote_call(pid, func, &[arg], entry);
+
+    // Too large for tcache and fastbins: once freed, its fd is the unsorted
+    // bin head, bin_at(1), which sits two size_t before main_arena.bins.
+    let p = call(malloc, SIZE_T * 128 * 2)?;
+    call(malloc, SIZE_T)?; // keeps p from merging into top
+    call(free, p)?;
+    let unsorted = ptrace::peek(pid, p)?;
+    let bins_off = malloc_state_generator(&libc_version, Arch::X64)
+        .offsetof("bins")
+        .unwrap_or(0) as u64;
+    let main_arena = unsorted.wrapping_sub(bins_off - 2 * SIZE_T);
+    if !proc
+        .libc_data_ranges()
+        .iter()
+        .any(|r| r.contains(&main_arena))
+    {
+        return Err(io::Error::other(format!(
+            "Unsorted bin fd 0x{:x} does not point into libc",
+            unsorted
+        )));
+    }
+
+    // A tcache entry gets next = NULL, or PROTECT_PTR(p, NULL) from 2.32 on.
+    let p = call(malloc, SIZE_T * 32)?;
+    ptrace::poke(pid, p, TCACHE_MARKER)?;
+    call(free, p)?;
+    let next = ptrace::peek(pid, p)?;
+    let tcache_enable = next == 0 || next == p >> 12;
+
+    // Neither mp_ nor global_max_fast is exported, so change each through
+    // mallopt and look for the libc word that took the new value.
+    let mallopt = libc_base + symbol(elf, "mallopt")?;
+    let mallopt = |param: u64, value: u64| -> io::Result<Option<u64>> {
+        let before = read_data(&proc);
+        ptrace::remote_call(pid, mallopt, &[param, value], entry)?;
+        let after = read_data(&proc);
+        Ok(changed_word(&before, &after, value))
+    };
+    let global_max_fast = mallopt(M_MXFAST, MXFAST_MARKER)?;
+    // trim_threshold is the first member of malloc_par.
+    let mp = mallopt(M_TRIM_THRESHOLD, TRIM_MARKER)?;
+
+    let par_def = malloc_par_generator(&libc_version, Arch::X64);
+    let par = mp.and_then(|addr| proc.read(addr, par_def.size()).map(|mem| par_def.new_instance(mem, addr)));
+    let tcache_param = |name: &str| {
+        par.as_ref()
+            .filter(|_| tcache_enable)
+            .and_then(|inst| inst.get_ptr(name))
+    };
+    let tcache_bins = tcache_param("tcache_bins");
+    let tcache_count = tcache_param("tcache_count");
+
+    Ok(LibcProfile {
+        build_id: elf.build_id(),
+        libc_version,
+        main_arena_offset: main_arena - libc_base,
+        mp_offset: mp.map(|a| a - libc_base),
+        global_max_fast_offset: global_max_fast.map(|a| a - libc_base),
+        io_list_all_offset: elf.symbol("_IO_list_all").map(|s| s.value),
+        tcache_enable,
+        tcache_bins,
+        tcache_count,
+    })
+}
+
+fn read_data(proc: &Proc) -> Vec<(u64, Vec<u8>)> {
+    proc.libc_data_ranges()
+        .into_iter()
+        .filter_map(|r| {
+            let mem = proc.read(r.start, (r.end - r.start) as usize)?;
+            Some((r.start, mem))
+        })
+        .collect()
+}
+
+/// Address of the only word that changed to `value` between two dumps.
+fn changed_word(before: &[(u64, Vec<u8>)], after: &[(u64, Vec<u8>)], value: u64) -> Option<u64> {
+    let mut hits = Vec::new();
+    for ((start, old), (_, new)) in before.iter().zip(after) {
+        for (i, (o, n)) in old.chunks_exact(8).zip(new.chunks_exact(8)).enumerate() {
+            if o != n && uk64(n) == value {
+                hits.push(start + (i * 8) as u64);
+            }
+        }
+    }
+    (hits.len() == 1).then(|| hits[0])
+}
+
+/// Steps the loader syscall by syscall until libc's code is mapped.
+fn wait_for_libc(pid: pid_t, proc: &Proc, libc: &str) -> io::Result<u64> {
+    loop {
+        if let Some(base) = proc.loaded_base(libc) {
+            return Ok(base);
+        }
+        ptrace::syscall(pid)?;
+        if ptrace::wait_stop(pid)?.is_none() {
+            return Err(io::Error::other("Probe exited before mapping libc"));
+        }
+    }
+}
+
+fn symbol(elf: &Elf, name: &str) -> io::Result<u64> {
+    elf.symbol(name)
+        .map(|s| s.value)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No {} in libc", name)))
+}
@@ -0,0 +1,834 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::common::uk64;
+use super::core::{
+    ArenaIssue, BinKind, BinOwner, BinSlot, Chain, MallocChunk, MallocPar, MallocState,
+    NextsizeIssue, Snapshot,
+};
+
+/// Which integrity check a chunk fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Check {
+    /// Size below MINSIZE, misaligned or out of range.
+    Size,
+    /// Chunk address not MALLOC_ALIGNMENT aligned.
+    Alignment,
+    /// PREV_INUSE of the next chunk disagrees with the chunk's state.
+    PrevInuse,
+    /// `fd->bk != p` or `bk->fd != p`.
+    Links,
+    /// Size of the physically next chunk.
+    NextSize,
+    /// `prev_size` disagrees with the size of the previous chunk.
+    PrevSize,
+    /// Chunk size does not belong in its fastbin.
+    FastbinIndex,
+    /// Unaligned tcache entry.
+    Tcache,
+    /// Large bin order or `fd_nextsize`/`bk_nextsize` links.
+    Nextsize,
+    /// Position or size of `top`.
+    Top,
+    /// `binmap` misses a non-empty bin.
+    Binmap,
+    /// The chunk walk ends before reaching `top`.
+    Walk,
+    /// `mp_.n_mmaps` disagrees with the mmapped chunks found.
+    Mmapped,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Size => "size",
+            Self::Alignment => "alignment",
+            Self::PrevInuse => "prev_inuse",
+            Self::Links => "links",
+            Self::NextSize => "next_size",
+            Self::PrevSize => "prev_size",
+            Self::FastbinIndex => "fastbin_index",
+            Self::Tcache => "tcache",
+            Self::Nextsize => "nextsize",
+            Self::Top => "top",
+            Self::Binmap => "binmap",
+            Self::Walk => "walk",
+            Self::Mmapped => "mmapped",
+        }
+    }
+}
+
+/// One failed check. `message` is what glibc aborts with when it runs into
+/// the chunk, if it checks this at all.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub addr: u64,
+    pub check: Check,
+    pub message: Option<&'static str>,
+    pub detail: String,
+}
+
+/// Replays glibc's integrity checks over every arena's heap and bins and
+/// every tcache in `snap`, ordered by chunk address.
+pub fn check(snap: &Snapshot) -> Vec<Violation> {
+    let record = snap.record();
+    let mut checker = Checker::new(snap, record.size_t as u64);
+
+    if let Some(state) = &record.main_arena {
+        checker.arena(&ArenaView {
+            owner: BinOwner::Main,
+            state,
+            heaps: snap.arena_heaps(state),
+            chunks: &record.heap_chunks,
+            fastbins: &record.fastbins,
+            unsorted: &record.unsortedbins,
+            smallbins: &record.smallbins,
+            largebins: &record.largebins,
+        });
+    }
+    for arena in &record.arenas {
+        checker.arena(&ArenaView {
+            owner: BinOwner::Arena(arena.addr),
+            state: &arena.state,
+            heaps: arena.heaps.clone(),
+            chunks: &arena.heap_chunks,
+            fastbins: &arena.fastbins,
+            unsorted: &arena.unsortedbins,
+            smallbins: &arena.smallbins,
+            largebins: &arena.largebins,
+        });
+    }
+
+    if let Some(tcache) = &record.tcache {
+        checker.tcache(BinOwner::Main, &snap.thread_tcache_chains(tcache));
+    }
+    let main_tcache = record.tcache.as_ref().map(|t| t.addr());
+    for thread in &record.threads {
+        if let Some(tcache) = &thread.tcache
+            && Some(tcache.addr()) != main_tcache
+        {
+            checker.tcache(BinOwner::Thread(thread.tid), &snap.thread_tcache_chains(tcache));
+        }
+    }
+
+    if let Some(mp) = &record.malloc_par {
+        checker.mmapped(mp, &record.mmapped_chunks);
+    }
+
+    let mut out = checker.out;
+    out.sort_by_key(|v| v.addr);
+    out
+}
+
+/// One arena's heap walk and bins.
+struct ArenaView<'a> {
+    owner: BinOwner,
+    state: &'a MallocState,
+    heaps: Vec<Range<u64>>,
+    chunks: &'a [MallocChunk],
+    fastbins: &'a HashMap<usize, Vec<MallocChunk>>,
+    unsorted: &'a [MallocChunk],
+    smallbins: &'a HashMap<usize, Vec<MallocChunk>>,
+    largebins: &'a HashMap<usize, Vec<MallocChunk>>,
+}
+
+struct Checker<'a> {
+    snap: &'a Snapshot,
+    size_t: u64,
+    align_mask: u64,
+    min_size: u64,
+    /// Largest fastbin chunk size, `global_max_fast`.
+    max_fast: u64,
+    out: Vec<Violation>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(snap: &'a Snapshot, size_t: u64) -> Self {
+        let align_mask = snap.malloc_alignment() - 1;
+        let mut checker = Self {
+            snap,
+            size_t,
+            align_mask,
+            min_size: (4 * size_t + align_mask) & !align_mask,
+            max_fast: 0,
+            out: Vec::new(),
+        };
+        // DEFAULT_MXFAST is 64 * SIZE_SZ / 4, set with set_max_fast.
+        let default_max_fast = (16 * size_t + size_t) & !align_mask;
+        checker.max_fast = snap
+            .info()
+            .symbols
+            .global_max_fast
+            .and_then(|off| checker.word(snap.libc_base() + off))
+            .unwrap_or(default_max_fast);
+        checker
+    }
+
+    fn word(&self, addr: u64) -> Option<u64> {
+        self.snap
+            .image()
+            .read(addr, self.size_t as usize)
+            .map(|mem| uk64(&mem))
+    }
+
+    fn report(&mut self, addr: u64, check: Check, message: Option<&'static str>, detail: String) {
+        self.out.push(Violation {
+            addr,
+            check,
+            message,
+            detail,
+        });
+    }
+
+    fn misaligned(&self, chunk: u64) -> bool {
+        (chunk + 2 * self.size_t) & self.align_mask != 0
+    }
+
+    fn arena(&mut self, arena: &ArenaView) {
+        let mut binned: HashMap<u64, BinSlot> = HashMap::new();
+        let lists = [
+            (BinKind::Unsorted, vec![(0, arena.unsorted)]),
+            (BinKind::Smallbin, indexed(arena.smallbins)),
+            (BinKind::Largebin, indexed(arena.largebins)),
+        ];
+        for (kind, bins) in lists {
+            for (index, chunks) in bins {
+                let slot = BinSlot {
+                    owner: arena.owner,
+                    kind,
+                    index,
+                };
+                for chunk in chunks {
+                    binned.entry(chunk.addr()).or_insert(slot);
+                    self.bin_links(arena.state, slot, chunk);
+                }
+            }
+        }
+        self.heap_walk(arena, &binned);
+        self.fastbins(arena);
+        self.largebin_rings(arena);
+        self.arena_state(arena);
+    }
+
+    fn arena_state(&mut self, arena: &ArenaView) {
+        for issue in self.snap.arena_issues(arena.state) {
+            let (addr, check, message) = match issue {
+                ArenaIssue::TopSize { top, .. } => {
+                    (top, Check::Top, Some("malloc(): corrupted top size"))
+                }
+                ArenaIssue::TopOutsideHeap { top }
+                | ArenaIssue::TopEnd { top, .. }
+                | ArenaIssue::TopPrevFree { top } => (top, Check::Top, None),
+                ArenaIssue::Unmarked { .. } => (arena.state.addr(), Check::Binmap, None),
+            };
+            self.report(addr, check, message, issue.to_string());
+        }
+    }
+
+    fn largebin_rings(&mut self, arena: &ArenaView) {
+        for (index, ring) in self.snap.arena_largebin_rings(arena.state) {
+            let slot = BinSlot {
+                owner: arena.owner,
+                kind: BinKind::Largebin,
+                index,
+            };
+            for issue in ring.issues {
+                let (check, message) = match issue {
+                    NextsizeIssue::BrokenLink { .. } => {
+                        (Check::Links, Some("corrupted double-linked list (not small)"))
+                    }
+                    _ => (Check::Nextsize, None),
+                };
+                self.report(issue.addr(), check, message, format!("{}: {}", slot, issue));
+            }
+        }
+    }
+
+    /// Checks the chunks of the linear heap walk against their neighbours.
+    fn heap_walk(&mut self, arena: &ArenaView, binned: &HashMap<u64, BinSlot>) {
+        let top = arena.state.top();
+        let system_mem = arena.state.system_mem().unwrap_or(u64::MAX);
+        for (i, chunk) in arena.chunks.iter().enumerate() {
+            let addr = chunk.addr();
+            let size = chunk.size();
+            if self.misaligned(addr) {
+                self.report(
+                    addr,
+                    Check::Alignment,
+                    Some("free(): invalid pointer"),
+                    "chunk is not aligned".to_string(),
+                );
+            }
+            if size < self.min_size || size & self.align_mask != 0 {
+                self.report(
+                    addr,
+                    Check::Size,
+                    Some("free(): invalid size"),
+                    format!("size 0x{:x}", size),
+                );
+            }
+            if !chunk.prev_inuse() && i > 0 {
+                let prev = &arena.chunks[i - 1];
+                let prev_size = chunk.prev_size();
+                let consolidated = self.snap.prev_chunk(chunk);
+                if consolidated.is_none_or(|c| c.addr() != prev.addr() || c.size() != prev_size) {
+                    self.report(
+                        addr,
+                        Check::PrevSize,
+                        Some("corrupted size vs. prev_size while consolidating"),
+                        format!(
+                            "prev_size 0x{:x}, previous chunk 0x{:x} has size 0x{:x}",
+                            prev_size,
+                            prev.addr(),
+                            prev.size()
+                        ),
+                    );
+                }
+            }
+            if addr == top {
+                continue;
+            }
+            self.successor(arena, system_mem, chunk, binned.get(&addr));
+        }
+
+        if let Some(last) = arena.chunks.last()
+            && last.addr() != top
+        {
+            let end = last.next_addr();
+            let size = self.snap.chunk_at(end).map(|c| c.size_field());
+            self.report(
+                end,
+                Check::Walk,
+                None,
+                format!(
+                    "chunk walk stops here (size {}), top is 0x{:x}",
+                    size.map_or("unreadable".to_string(), |s| format!("0x{:x}", s)),
+                    top
+                ),
+            );
+        }
+    }
+
+    /// Checks made on the chunk after `chunk` when `chunk` is freed or taken
+    /// out of a bin.
+    fn successor(
+        &mut self,
+        arena: &ArenaView,
+        system_mem: u64,
+        chunk: &MallocChunk,
+        slot: Option<&BinSlot>,
+    ) {
+        let (addr, size, next) = (chunk.addr(), chunk.size(), chunk.next_addr());
+        if !arena.heaps.iter().any(|r| r.contains(&next)) {
+            self.report(
+                addr,
+                Check::NextSize,
+                Some("double free or corruption (out)"),
+                format!("next chunk 0x{:x} is outside the heap", next),
+            );
+            return;
+        }
+        let Some(next_chunk) = self.snap.next_chunk(chunk) else {
+            return;
+        };
+        let next_chunk_size = next_chunk.size();
+        if next_chunk_size <= 2 * self.size_t || next_chunk_size >= system_mem {
+            let message = match slot.map(|s| s.kind) {
+                Some(BinKind::Unsorted) => "malloc(): invalid next size (unsorted)",
+                _ if size <= self.max_fast => "free(): invalid next size (fast)",
+                _ => "free(): invalid next size (normal)",
+            };
+            self.report(
+                addr,
+                Check::NextSize,
+                Some(message),
+                format!("next chunk 0x{:x} has size 0x{:x}", next, next_chunk_size),
+            );
+            return;
+        }
+
+        let next_inuse = next_chunk.prev_inuse();
+        let next_prev_size = next_chunk.prev_size();
+        match slot {
+            Some(slot) => {
+                let unsorted = slot.kind == BinKind::Unsorted;
+                if next_inuse {
+                    self.report(
+                        addr,
+                        Check::PrevInuse,
+                        unsorted.then_some("malloc(): invalid next->prev_inuse (unsorted)"),
+                        format!("free in {} but next chunk 0x{:x} has PREV_INUSE set", slot, next),
+                    );
+                }
+                if next_prev_size != size {
+                    let message = if unsorted {
+                        "malloc(): mismatching next->prev_size (unsorted)"
+                    } else {
+                        "corrupted size vs. prev_size"
+                    };
+                    self.report(
+                        addr,
+                        Check::PrevSize,
+                        Some(message),
+                        format!(
+                            "size 0x{:x}, next chunk 0x{:x} has prev_size 0x{:x}",
+                            size, next, next_prev_size
+                        ),
+                    );
+                }
+            }
+            None if !next_inuse => self.report(
+                addr,
+                Check::PrevInuse,
+                Some("double free or corruption (!prev)"),
+                format!("not in a bin but next chunk 0x{:x} has PREV_INUSE clear", next),
+            ),
+            None => {}
+        }
+    }
+
+    /// `fd->bk == p && bk->fd == p` for a chunk in a doubly linked bin.
+    fn bin_links(&mut self, state: &MallocState, slot: BinSlot, chunk: &MallocChunk) {
+        let addr = chunk.addr();
+        let (fd, bk) = (chunk.fd(), chunk.bk());
+        let fd_bk = self.word(fd + 3 * self.size_t);
+        let bk_fd = self.word(bk + 2 * self.size_t);
+        if fd_bk != Some(addr) || bk_fd != Some(addr) {
+            let message = match slot.kind {
+                BinKind::Unsorted => "malloc(): unsorted double linked list corrupted",
+                BinKind::Smallbin if bk_fd != Some(addr) => {
+                    "malloc(): smallbin double linked list corrupted"
+                }
+                _ => "corrupted double-linked list",
+            };
+            let show = |v: Option<u64>| v.map_or("unmapped".to_string(), |v| format!("0x{:x}", v));
+            self.report(
+                addr,
+                Check::Links,
+                Some(message),
+                format!(
+                    "{}: fd 0x{:x} has bk {}, bk 0x{:x} has fd {}",
+                    slot,
+                    fd,
+                    show(fd_bk),
+                    bk,
+                    show(bk_fd)
+                ),
+            );
+        }
+
+        let size = chunk.size();
+        let system_mem = state.system_mem().unwrap_or(u64::MAX);
+        if slot.kind == BinKind::Unsorted && (size <= 2 * self.size_t || size > system_mem) {
+            self.report(
+                addr,
+                Check::Size,
+                Some("malloc(): invalid size (unsorted)"),
+                format!("size 0x{:x} in {}", size, slot),
+            );
+        }
+    }
+
+    fn fastbins(&mut self, arena: &ArenaView) {
+        let chains = self.snap.arena_fastbin_chains(arena.state);
+        // Pointer alignment is checked since safe-linking came in.
+        let checked = self.snap.safe_linking();
+        for (index, chunks) in indexed(arena.fastbins) {
+            let slot = BinSlot {
+                owner: arena.owner,
+                kind: BinKind::Fastbin,
+                index,
+            };
+            for (i, chunk) in chunks.iter().enumerate() {
+                let size = chunk.size();
+                let expected = self.fastbin_index(size);
+                if expected != Some(index) {
+                    self.report(
+                        chunk.addr(),
+                        Check::FastbinIndex,
+                        Some("malloc(): memory corruption (fast)"),
+                        format!("size 0x{:x} in {}", size, slot),
+                    );
+                }
+                if i == 0 && self.misaligned(chunk.addr()) {
+                    self.report(
+                        chunk.addr(),
+                        Check::Alignment,
+                        checked.then_some("malloc(): unaligned fastbin chunk detected 2"),
+                        format!("head of {} is not aligned", slot),
+                    );
+                }
+            }
+            if let Some(chain) = chains.get(&index) {
+                let message = checked.then_some("malloc(): unaligned fastbin chunk detected 3");
+                self.misaligned_links(chain, slot, Check::Alignment, message);
+            }
+        }
+    }
+
+    /// `mp_.n_mmaps` counts the chunks malloc currently has mmapped.
+    fn mmapped(&mut self, mp: &MallocPar, chunks: &[MallocChunk]) {
+        if let Some(n_mmaps) = mp.n_mmaps()
+            && n_mmaps as usize != chunks.len()
+        {
+            self.report(
+                mp.addr(),
+                Check::Mmapped,
+                None,
+                format!("mp_.n_mmaps is {}, but {} mmapped chunks were found", n_mmaps, chunks.len()),
+            );
+        }
+    }
+
+    fn tcache(&mut self, owner: BinOwner, chains: &HashMap<usize, Chain>) {
+        let checked = self.snap.safe_linking();
+        let message = checked.then_some("malloc(): unaligned tcache chunk detected");
+        for (&index, chain) in chains {
+            let slot = BinSlot {
+                owner,
+                kind: BinKind::Tcache,
+                index,
+            };
+            if let Some(&head) = chain.addrs.first()
+                && self.misaligned(head)
+            {
+                self.report(
+                    head,
+                    Check::Tcache,
+                    message,
+                    format!("head of {} is not aligned", slot),
+                );
+            }
+            self.misaligned_links(chain, slot, Check::Tcache, message);
+        }
+    }
+
+    /// Reports every chunk of `chain` whose stored pointer decodes to an
+    /// unaligned address.
+    fn misaligned_links(
+        &mut self,
+        chain: &Chain,
+        slot: BinSlot,
+        check: Check,
+        message: Option<&'static str>,
+    ) {
+        for (addr, link) in chain.addrs.iter().zip(&chain.links) {
+            if link.misaligned {
+                self.report(
+                    *addr,
+                    check,
+                    message,
+                    format!("{}: next 0x{:x} is not aligned", slot, link.decoded),
+                );
+            }
+        }
+    }
+
+    /// glibc's `fastbin_index` when `size` is a fastbin size.
+    fn fastbin_index(&self, size: u64) -> Option<usize> {
+        if size < self.min_size || size > self.max_fast {
+            return None;
+        }
+        let shift = if self.size_t == 8 { 4 } else { 3 };
+        usize::try_from((size >> shift).checked_sub(2)?).ok()
+    }
+}
+
+fn indexed(bins: &HashMap<usize, Vec<MallocChunk>>) -> Vec<(usize, &[MallocChunk])> {
+    let mut out: Vec<(usize, &[MallocChunk])> =
+        bins.iter().map(|(&i, c)| (i, c.as_slice())).collect();
+    out.sort_by_key(|(i, _)| *i);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internals::common::Arch;
+    use crate::internals::core::TargetInfo;
+    use crate::internals::image::{MemoryImage, Region};
+    use crate::internals::proc::{Map, Maps};
+    use crate::internals::structs::{CStructDef, malloc_state_generator};
+
+    const LIBC: u64 = 0x7f00_0000_0000;
+    const HEAP: u64 = 0x5555_5555_9000;
+    const HEAP_SIZE: u64 = 0x1000;
+    /// The first chunk after the `tcache_perthread_struct` chunk.
+    const FIRST: u64 = HEAP + 0x290;
+
+    fn snapshot(arch: Arch) -> Snapshot {
+        Snapshot::new(TargetInfo::sample(arch), MemoryImage::default())
+    }
+
+    /// A glibc 2.35 x86_64 process: `main_arena` at the start of libc's
+    /// data with every bin empty, and a `[heap]` that starts with the
+    /// tcache chunk.
+    struct Heap {
+        state: CStructDef,
+        arena: Vec<u8>,
+        heap: Vec<u8>,
+    }
+
+    impl Heap {
+        fn new() -> Self {
+            let mut heap = Self {
+                state: malloc_state_generator("2.35", Arch::X64),
+                arena: vec![0; 0x1000],
+                heap: vec![0; HEAP_SIZE as usize],
+            };
+            for index in 1..128 {
+                heap.bin(index, None);
+            }
+            heap.set_arena("next", LIBC);
+            heap.set_arena("system_mem", HEAP_SIZE);
+            heap.chunk(HEAP, 0, 0x291);
+            heap
+        }
+
+        fn arena_addr(&self, field: &str) -> u64 {
+            self.state
+                .new_instance(Vec::new(), LIBC)
+                .addrof(field)
+                .unwrap()
+        }
+
+        fn set_arena(&mut self, field: &str, value: u64) {
+            let off = (self.arena_addr(field) - LIBC) as usize;
+            self.arena[off..off + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        fn set(&mut self, addr: u64, value: u64) {
+            let off = (addr - HEAP) as usize;
+            self.heap[off..off + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        fn chunk(&mut self, addr: u64, prev_size: u64, size: u64) {
+            self.set(addr, prev_size);
+            self.set(addr + 8, size);
+        }
+
+        /// The chunk at `addr` runs to the end of the heap and is `top`.
+        fn top(&mut self, addr: u64) {
+            self.chunk(addr, 0, (HEAP + HEAP_SIZE - addr) | 1);
+            self.set_arena("top", addr);
+        }
+
+        /// Makes `chunk` the only chunk of bin `index`, or empties the bin.
+        fn bin(&mut self, index: usize, chunk: Option<u64>) {
+            let fd = format!("bins[{}]", (index - 1) * 2);
+            let bk = format!("bins[{}]", (index - 1) * 2 + 1);
+            let bin = self.arena_addr(&fd) - 0x10;
+            let first = chunk.unwrap_or(bin);
+            self.set_arena(&fd, first);
+            self.set_arena(&bk, first);
+            if let Some(chunk) = chunk {
+                self.set(chunk + 0x10, bin);
+                self.set(chunk + 0x18, bin);
+            }
+            if chunk.is_some() && index > 1 {
+                let word = (self.arena_addr(&format!("binmap[{}]", index >> 5)) - LIBC) as usize;
+                self.arena[word + (index & 31) / 8] |= 1 << (index & 7);
+            }
+        }
+
+        /// A safe-linked `next` pointer stored at `pos`.
+        fn protect(pos: u64, next: u64) -> u64 {
+            (pos >> 12) ^ next
+        }
+
+        fn check(self) -> Vec<(u64, Check, Option<&'static str>)> {
+            let maps = vec![
+                Map::new(HEAP..HEAP + HEAP_SIZE, "rw-p", "[heap]"),
+                Map::new(
+                    LIBC..LIBC + 0x1000,
+                    "rw-p",
+                    "/usr/lib/x86_64-linux-gnu/libc.so.6",
+                ),
+            ];
+            let regions = vec![
+                Region {
+                    addr: HEAP,
+                    data: self.heap,
+                },
+                Region {
+                    addr: LIBC,
+                    data: self.arena,
+                },
+            ];
+            let image = MemoryImage::new(Maps::new(maps), regions, Vec::new());
+            let snap = Snapshot::new(TargetInfo::sample(Arch::X64), image);
+            check(&snap)
+                .into_iter()
+                .map(|v| (v.addr, v.check, v.message))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn consistent_heap() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x91);
+        heap.chunk(FIRST + 0x90, 0x90, 0x20);
+        heap.bin(1, Some(FIRST));
+        heap.top(FIRST + 0xb0);
+        assert_eq!(heap.check(), vec![]);
+    }
+
+    #[test]
+    fn misaligned_size() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x28 | 1);
+        heap.top(FIRST + 0x20);
+        assert_eq!(
+            heap.check(),
+            vec![
+                (FIRST, Check::Size, Some("free(): invalid size")),
+                (
+                    FIRST,
+                    Check::NextSize,
+                    Some("free(): invalid next size (fast)")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn prev_inuse_mismatch() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x21);
+        heap.chunk(FIRST + 0x20, 0x20, 0x20);
+        heap.top(FIRST + 0x40);
+        assert_eq!(
+            heap.check(),
+            vec![(
+                FIRST,
+                Check::PrevInuse,
+                Some("double free or corruption (!prev)")
+            )]
+        );
+    }
+
+    #[test]
+    fn corrupted_double_linked_list() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x21);
+        heap.chunk(FIRST + 0x20, 0, 0x91);
+        heap.chunk(FIRST + 0xb0, 0x90, 0x20);
+        heap.bin(9, Some(FIRST + 0x20));
+        // fd now leads to the in-use chunk, whose bk is not p.
+        heap.set(FIRST + 0x30, FIRST);
+        heap.top(FIRST + 0xd0);
+        assert_eq!(
+            heap.check(),
+            vec![(
+                FIRST + 0x20,
+                Check::Links,
+                Some("corrupted double-linked list")
+            )]
+        );
+    }
+
+    #[test]
+    fn prev_size_mismatch() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x91);
+        heap.chunk(FIRST + 0x90, 0x80, 0x20);
+        heap.bin(1, Some(FIRST));
+        heap.top(FIRST + 0xb0);
+        assert_eq!(
+            heap.check(),
+            vec![
+                (
+                    FIRST,
+                    Check::PrevSize,
+                    Some("malloc(): mismatching next->prev_size (unsorted)")
+                ),
+                (
+                    FIRST + 0x90,
+                    Check::PrevSize,
+                    Some("corrupted size vs. prev_size while consolidating")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_next_size() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x91);
+        heap.chunk(FIRST + 0x90, 0x90, 0x2000);
+        heap.bin(1, Some(FIRST));
+        heap.top(FIRST + 0xb0);
+        assert_eq!(
+            heap.check(),
+            vec![
+                (
+                    FIRST,
+                    Check::NextSize,
+                    Some("malloc(): invalid next size (unsorted)")
+                ),
+                (FIRST + 0x90, Check::Walk, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn fastbin_size_vs_index() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x31);
+        heap.set(FIRST + 0x10, Heap::protect(FIRST + 0x10, 0));
+        heap.set_arena("fastbinsY[0]", FIRST);
+        heap.top(FIRST + 0x30);
+        assert_eq!(
+            heap.check(),
+            vec![(
+                FIRST,
+                Check::FastbinIndex,
+                Some("malloc(): memory corruption (fast)")
+            )]
+        );
+    }
+
+    #[test]
+    fn unaligned_tcache_entry() {
+        let mut heap = Heap::new();
+        heap.chunk(FIRST, 0, 0x21);
+        heap.chunk(FIRST + 0x20, 0, 0x21);
+        heap.set(FIRST + 0x10, Heap::protect(FIRST + 0x10, FIRST + 0x38));
+        // counts[0] and entries[0] of the tcache_perthread_struct.
+        heap.heap[0x10] = 1;
+        heap.set(HEAP + 0x90, FIRST + 0x10);
+        heap.top(FIRST + 0x40);
+        assert_eq!(
+            heap.check(),
+            vec![(
+                FIRST,
+                Check::Tcache,
+                Some("malloc(): unaligned tcache chunk detected")
+            )]
+        );
+    }
+
+    #[test]
+    fn fastbin_index_x64() {
+        let snap = snapshot(Arch::X64);
+        let checker = Checker::new(&snap, 8);
+        assert_eq!(checker.fastbin_index(0x10), None);
+        assert_eq!(checker.fastbin_index(0x20), Some(0));
+        assert_eq!(checker.fastbin_index(0x30), Some(1));
+        assert_eq!(checker.fastbin_index(0x80), Some(6));
+        assert_eq!(checker.fastbin_index(0x90), None);
+    }
+
+    #[test]
+    fn fastbin_index_i386() {
+        // MALLOC_ALIGNMENT is 16, so only every other index is used.
+        let snap = snapshot(Arch::X86);
+        let checker = Checker::new(&snap, 4);
+        assert_eq!(checker.fastbin_index(0x8), None);
+        assert_eq!(checker.fastbin_index(0x10), Some(0));
+        assert_eq!(checker.fastbin_index(0x20), Some(2));
+        assert_eq!(checker.fastbin_index(0x40), Some(6));
+        assert_eq!(checker.fastbin_index(0x50), None);
+    }
+}
@@ -10,7 +10,7 @@ pub enum Arch {
 }
 
 pub fn get_arch(path: &str) -> io::Result<Arch> {
-    let mut fd = File::open(&path).map_err(|e| {
+    let mut fd = File::open(path).map_err(|e| {
         io::Error::new(
             e.kind(), 
             format!("Failed to open file {}: {}", path, e))
@@ -32,6 +32,23 @@ pub fn get_arch(path: &str) -> io::Result<Arch> {
     }
 }
 
+pub fn parse_u64(input: &str) -> Option<u64> {
+    if let Some(rest) = input.strip_prefix("0x") {
+        u64::from_str_radix(rest, 16).ok()
+    } else {
+        input.parse::<u64>().ok()
+    }
+}
+
+/// Parses a glibc version string such as `2.41` or `2.35-0ubuntu3` into
+/// `(major, minor)`.
+pub fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
 pub fn uk64<T: AsRef<[u8]>>(bytes: T) -> u64 {
     let bytes = bytes.as_ref();
     let mut buf = [0; 8];
@@ -40,6 +57,7 @@ pub fn uk64<T: AsRef<[u8]>>(bytes: T) -> u64 {
     u64::from_le_bytes(buf)
 }
 
+#[allow(dead_code)]
 pub fn pk64(num: u64) -> [u8; 8] {
     num.to_le_bytes()
 } 
@@ -1,15 +1,48 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 
-use super::common::{uk64, Arch};
+use super::common::{parse_version, uk64, Arch};
+use super::elf::Elf;
+use super::freeze::{Freeze, FreezeMethod};
+use super::image::MemoryImage;
 use super::proc::Proc;
+use super::profile::{self, LibcProfile};
+use super::resolve::{self, LibcSymbols};
 use super::structs::{
-    malloc_chunk_generator, malloc_state_generator, tcache_struct_generator, CStructDef,
-    CStructInstance,
+    heap_info_generator, malloc_alignment, malloc_chunk_generator, malloc_par_generator,
+    malloc_state_generator, tcache_struct_generator, CStructDef, CStructInstance,
 };
 
+const PAGE_SIZE: u64 = 0x1000;
+/// Times `HeapInspector::capture` follows pointers into memory it has not
+/// read yet. Each round reaches one more arena, heap or listed chunk.
+const CAPTURE_ROUNDS: usize = 16;
+
+/// Flag bits of the chunk size field.
+pub const PREV_INUSE: u64 = 0x1;
+pub const IS_MMAPPED: u64 = 0x2;
+pub const NON_MAIN_ARENA: u64 = 0x4;
+const SIZE_BITS: u64 = PREV_INUSE | IS_MMAPPED | NON_MAIN_ARENA;
+
+/// glibc's `chunksize`: a size field without its flag bits.
+pub fn chunksize(size_field: u64) -> u64 {
+    size_field & !SIZE_BITS
+}
+
+/// `malloc_state.flags` bits. FASTCHUNKS_BIT is only used before 2.27.
+pub const FASTCHUNKS_BIT: u32 = 0x1;
+pub const NONCONTIGUOUS_BIT: u32 = 0x2;
+const BINMAPSIZE: usize = 4;
+
+/// glibc bin numbers: `bin_at(1)` is the unsorted bin, then 62 small bins
+/// and 64 large ones.
+const UNSORTED_BIN: usize = 1;
+const SMALLBINS: Range<usize> = 2..64;
+const LARGEBINS: Range<usize> = 64..128;
+
 #[derive(Debug, Clone)]
 pub struct MallocState {
     inst: CStructInstance,
@@ -22,6 +55,10 @@ impl MallocState {
         }
     }
 
+    pub fn addr(&self) -> u64 {
+        self.inst.addr()
+    }
+
     pub fn addrof(&self, name: &str) -> Option<u64> {
         self.inst.addrof(name)
     }
@@ -29,6 +66,158 @@ impl MallocState {
     pub fn fastbins(&self) -> Option<Vec<u64>> {
         self.inst.get_ptr_array("fastbinsY")
     }
+
+    /// `bk` of glibc bin `index` (1 is the unsorted bin), the last chunk in
+    /// it (or the head when empty).
+    fn bin_bk(&self, index: usize) -> Option<u64> {
+        self.inst.get_ptr(&format!("bins[{}]", (index - 1) * 2 + 1))
+    }
+
+    pub fn top(&self) -> u64 {
+        self.inst.get_ptr("top").unwrap_or(0)
+    }
+
+    pub fn last_remainder(&self) -> u64 {
+        self.inst.get_ptr("last_remainder").unwrap_or(0)
+    }
+
+    /// Next arena in the ring that starts and ends at `main_arena`.
+    pub fn next(&self) -> u64 {
+        self.inst.get_ptr("next").unwrap_or(0)
+    }
+
+    pub fn system_mem(&self) -> Option<u64> {
+        self.inst.get_ptr("system_mem")
+    }
+
+    pub fn max_system_mem(&self) -> Option<u64> {
+        self.inst.get_ptr("max_system_mem")
+    }
+
+    pub fn flags(&self) -> Option<u32> {
+        self.inst.get_int("flags")
+    }
+
+    /// MORECORE does not return contiguous memory, e.g. every thread arena.
+    #[allow(dead_code)]
+    pub fn noncontiguous(&self) -> bool {
+        self.flags().is_some_and(|f| f & NONCONTIGUOUS_BIT != 0)
+    }
+
+    /// Before 2.27 this was FASTCHUNKS_BIT of `flags`, set when there are
+    /// none.
+    pub fn have_fastchunks(&self) -> Option<bool> {
+        match self.inst.get_int("have_fastchunks") {
+            Some(v) => Some(v != 0),
+            None => self.flags().map(|f| f & FASTCHUNKS_BIT == 0),
+        }
+    }
+
+    pub fn binmap(&self) -> Option<Vec<u32>> {
+        (0..BINMAPSIZE)
+            .map(|i| self.inst.get_int(&format!("binmap[{}]", i)))
+            .collect()
+    }
+
+    /// Whether glibc bin `index` is marked in `binmap`. Bits are set when a
+    /// chunk is sorted into the bin and only cleared lazily.
+    pub fn bin_marked(&self, index: usize) -> Option<bool> {
+        let word = self.inst.get_int(&format!("binmap[{}]", index >> 5))?;
+        Some(word & (1 << (index & 31)) != 0)
+    }
+}
+
+/// Header of one mmapped heap owned by a thread arena.
+#[derive(Debug, Clone)]
+pub struct HeapInfo {
+    inst: CStructInstance,
+}
+
+impl HeapInfo {
+    fn new(def: &CStructDef, mem: Vec<u8>, addr: u64) -> Self {
+        Self {
+            inst: def.new_instance(mem, addr),
+        }
+    }
+
+    pub fn addr(&self) -> u64 {
+        self.inst.addr()
+    }
+
+    pub fn ar_ptr(&self) -> u64 {
+        self.inst.get_ptr("ar_ptr").unwrap_or(0)
+    }
+
+    /// Previous heap of the same arena, 0 for the first one.
+    pub fn prev(&self) -> u64 {
+        self.inst.get_ptr("prev").unwrap_or(0)
+    }
+
+    /// Bytes of the heap currently in use (read/write).
+    pub fn size(&self) -> u64 {
+        self.inst.get_ptr("size").unwrap_or(0)
+    }
+}
+
+/// glibc's `malloc_par`, i.e. the `mp_` tunables and counters.
+#[derive(Debug, Clone)]
+pub struct MallocPar {
+    inst: CStructInstance,
+}
+
+impl MallocPar {
+    fn new(def: &CStructDef, mem: Vec<u8>, addr: u64) -> Self {
+        Self {
+            inst: def.new_instance(mem, addr),
+        }
+    }
+
+    pub fn addr(&self) -> u64 {
+        self.inst.addr()
+    }
+
+    pub fn trim_threshold(&self) -> Option<u64> {
+        self.inst.get_ptr("trim_threshold")
+    }
+
+    pub fn mmap_threshold(&self) -> Option<u64> {
+        self.inst.get_ptr("mmap_threshold")
+    }
+
+    pub fn arena_max(&self) -> Option<u64> {
+        self.inst.get_ptr("arena_max")
+    }
+
+    /// Huge page size used for heaps, 0 without hugetlb; `None` before
+    /// glibc 2.35.
+    pub fn hp_pagesize(&self) -> Option<u64> {
+        self.inst.get_ptr("hp_pagesize")
+    }
+
+    pub fn n_mmaps(&self) -> Option<u32> {
+        self.inst.get_int("n_mmaps")
+    }
+
+    pub fn max_n_mmaps(&self) -> Option<u32> {
+        self.inst.get_int("max_n_mmaps")
+    }
+
+    pub fn sbrk_base(&self) -> Option<u64> {
+        self.inst.get_ptr("sbrk_base")
+    }
+
+    /// `None` for glibc built without tcache.
+    pub fn tcache_bins(&self) -> Option<u64> {
+        self.inst.get_ptr("tcache_bins")
+    }
+
+    pub fn tcache_count(&self) -> Option<u64> {
+        self.inst.get_ptr("tcache_count")
+    }
+
+    pub fn tcache_unsorted_limit(&self) -> Option<u64> {
+        self.inst.get_ptr("tcache_unsorted_limit")
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +236,10 @@ impl MallocChunk {
         self.inst.addr()
     }
 
+    pub fn addrof(&self, name: &str) -> Option<u64> {
+        self.inst.addrof(name)
+    }
+
     pub fn fd(&self) -> u64 {
         self.inst.get_ptr("fd").unwrap_or(0)
     }
@@ -55,9 +248,67 @@ impl MallocChunk {
         self.inst.get_ptr("bk").unwrap_or(0)
     }
 
-    pub fn size(&self) -> u64 {
+    pub fn fd_nextsize(&self) -> u64 {
+        self.inst.get_ptr("fd_nextsize").unwrap_or(0)
+    }
+
+    pub fn bk_nextsize(&self) -> u64 {
+        self.inst.get_ptr("bk_nextsize").unwrap_or(0)
+    }
+
+    pub fn prev_size(&self) -> u64 {
+        self.inst.get_ptr("prev_size").unwrap_or(0)
+    }
+
+    /// The size field as stored, flag bits included.
+    pub fn size_field(&self) -> u64 {
         self.inst.get_ptr("size").unwrap_or(0)
     }
+
+    /// Chunk size without the flag bits.
+    pub fn size(&self) -> u64 {
+        chunksize(self.size_field())
+    }
+
+    pub fn prev_inuse(&self) -> bool {
+        self.size_field() & PREV_INUSE != 0
+    }
+
+    pub fn is_mmapped(&self) -> bool {
+        self.size_field() & IS_MMAPPED != 0
+    }
+
+    pub fn non_main_arena(&self) -> bool {
+        self.size_field() & NON_MAIN_ARENA != 0
+    }
+
+    /// The user pointer malloc returns for this chunk.
+    pub fn mem(&self) -> u64 {
+        self.addr() + 2 * self.size_t()
+    }
+
+    /// User bytes of the chunk while in use, as `malloc_usable_size`
+    /// reports them; a heap chunk also owns the next chunk's `prev_size`.
+    pub fn usable_size(&self) -> u64 {
+        let overhead = if self.is_mmapped() { 2 } else { 1 };
+        self.size().saturating_sub(overhead * self.size_t())
+    }
+
+    /// Address of the physically next chunk.
+    pub fn next_addr(&self) -> u64 {
+        self.addr() + self.size()
+    }
+
+    /// Address of the physically previous chunk, known only while that
+    /// chunk is free.
+    pub fn prev_addr(&self) -> Option<u64> {
+        (!self.prev_inuse() && !self.is_mmapped())
+            .then(|| self.addr().wrapping_sub(self.prev_size()))
+    }
+
+    fn size_t(&self) -> u64 {
+        self.inst.ptr_size() as u64
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -72,163 +323,937 @@ impl Tcache {
         }
     }
 
+    pub fn addr(&self) -> u64 {
+        self.inst.addr()
+    }
+
     pub fn entries(&self) -> Option<Vec<u64>> {
         self.inst.get_ptr_array("entries")
     }
+
+    /// Per-bin counts, whether glibc stores them as `char` or `uint16_t`.
+    pub fn counts(&self) -> Vec<u16> {
+        let bins = self.entries().map(|e| e.len()).unwrap_or(0);
+        (0..bins)
+            .map_while(|i| {
+                let var = format!("counts[{}]", i);
+                self.inst
+                    .get_u16(&var)
+                    .or_else(|| self.inst.get_u8(&var).map(u16::from))
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct HeapInspectorConfig {
     pub main_arena_offset: u64,
-    pub tcache_enable: bool,
+    /// `None` detects tcache from the libc and the target's state.
+    pub tcache_enable: Option<bool>,
     pub libc_version: Option<String>,
+    pub debug_dir: Option<PathBuf>,
+    /// Overrides the libc path named in the target's mappings.
+    pub libc_path: Option<String>,
+    /// Stops a live target for the duration of each capture.
+    pub freeze: Option<FreezeMethod>,
+}
+
+/// State of one list during `walk_lists`.
+#[derive(Default)]
+struct ListWalk {
+    chunks: Vec<MallocChunk>,
+    links: Vec<Link>,
+    seen: HashSet<u64>,
+    cycle: bool,
+}
+
+impl ListWalk {
+    fn finish(self) -> (Vec<MallocChunk>, Chain) {
+        let addrs = self.chunks.iter().map(|c| c.addr()).collect();
+        let chain = Chain {
+            addrs,
+            links: self.links,
+            cycle: self.cycle,
+        };
+        (self.chunks, chain)
+    }
 }
 
+/// A forward pointer as stored in a free chunk and as glibc interprets it.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub raw: u64,
+    pub decoded: u64,
+    pub misaligned: bool,
+    pub unmapped: bool,
+}
+
+/// `links[i]` is the pointer stored in the chunk at `addrs[i]`.
 #[derive(Debug, Clone)]
 pub struct Chain {
     pub addrs: Vec<u64>,
+    pub links: Vec<Link>,
     pub cycle: bool,
 }
 
-impl Default for HeapInspectorConfig {
-    fn default() -> Self {
+/// What setup learned about the target and its libc: everything a
+/// `Snapshot` needs besides the captured memory.
+#[derive(Debug, Clone)]
+pub struct TargetInfo {
+    pub pid: u64,
+    pub arch: Arch,
+    pub libc_version: String,
+    pub tcache_enable: bool,
+    pub main_arena_offset: u64,
+    pub mp_offset: Option<u64>,
+    /// `mp_.tcache_bins` from the libc profile, for when `mp_` cannot be read.
+    pub tcache_bins: Option<u64>,
+    pub libc_path: Option<String>,
+    pub ld_path: Option<String>,
+    pub exe_path: PathBuf,
+    pub symbols: LibcSymbols,
+    /// Size of libc's TLS block, scanned when `symbols` has no `tcache`.
+    pub tls_size: u64,
+    /// Problems noticed while setting up that did not prevent inspection.
+    pub warnings: Vec<String>,
+}
+
+#[cfg(test)]
+impl TargetInfo {
+    /// A glibc 2.35 target with nothing resolved, for tests to fill in.
+    pub fn sample(arch: Arch) -> Self {
         Self {
+            pid: 1,
+            arch,
+            libc_version: "2.35".to_string(),
+            tcache_enable: true,
             main_arena_offset: 0,
-            tcache_enable: false,
-            libc_version: None,
+            mp_offset: None,
+            tcache_bins: None,
+            libc_path: None,
+            ld_path: None,
+            exe_path: PathBuf::new(),
+            symbols: LibcSymbols::default(),
+            tls_size: 0x100,
+            warnings: Vec::new(),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct HeapInspector {
-    pid: u64,
     proc: Proc,
-    arch: Arch,
-    size_t: usize,
-    libc_version: String,
-    tcache_enable: bool,
-    main_arena_offset: u64,
-    libc_base: u64,
-    heap_base: u64,
-    libc_path: Option<String>,
-    ld_path: Option<String>,
-    exe_path: PathBuf,
-    malloc_state: CStructDef,
-    malloc_chunk: CStructDef,
-    tcache_struct: CStructDef,
+    info: TargetInfo,
+    profile: Option<LibcProfile>,
+    freeze: Option<FreezeMethod>,
 }
 
 impl HeapInspector {
     pub fn new(pid: u64, config: HeapInspectorConfig) -> io::Result<Self> {
-        let proc = Proc::new(pid);
+        Self::with_proc(Proc::new(pid), config)
+    }
+
+    /// Inspects the process recorded in an ELF core file. Pass the
+    /// target's libc as `config.libc_path` when the path the core names
+    /// does not hold the same libc build on this machine.
+    pub fn from_core(path: &str, config: HeapInspectorConfig) -> io::Result<Self> {
+        let proc = Proc::from_core(path, config.libc_path.as_deref())?;
+        Self::with_proc(proc, config)
+    }
+
+    fn with_proc(proc: Proc, config: HeapInspectorConfig) -> io::Result<Self> {
+        let pid = proc.pid();
         let arch = proc.arch();
-        if arch != Arch::X64 {
-            return Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "Only x86_64 is supported in this port",
-            ));
-        }
 
-        let bases = proc.bases();
-        let libc_base = first_base(&bases, "libc");
-        let heap_base = first_base(&bases, "heap");
-        let (default_libc, default_ld) = default_lib_paths();
-        let libc_path = proc.libc_path().or(default_libc);
-        let ld_path = proc.ld_path().or(default_ld);
+        let maps = proc.maps();
+        let libc_base = first_base(&maps.bases(), "libc");
+        let mut warnings = Vec::new();
+        // A core may come from another machine, so the host's libraries
+        // are never assumed for it.
+        let (default_libc, default_ld) = if proc.is_core() {
+            (None, None)
+        } else {
+            default_lib_paths(arch)
+        };
+        let libc_path = match config.libc_path {
+            Some(path) => Some(path),
+            None if proc.is_core() => core_libc(&proc, libc_base, maps.libc(), &mut warnings),
+            None => maps.libc().or(default_libc),
+        };
+        let ld_path = maps.ld().or(default_ld);
         let exe_path = proc.exe_path();
+        let profile = libc_path.as_deref().and_then(profile::load_for);
+        let detected_version = resolve::libc_version(&proc, libc_path.as_deref());
+        if let (Some(p), Some(v)) = (&profile, &detected_version)
+            && &p.libc_version != v
+        {
+            warnings.push(format!(
+                "profile {} says glibc {}, but the mapped libc reports {}",
+                p.build_id.as_deref().unwrap_or("(no build-id)"),
+                p.libc_version,
+                v
+            ));
+        }
         let libc_version = config
             .libc_version
+            .or(detected_version)
+            .or_else(|| profile.as_ref().map(|p| p.libc_version.clone()))
             .unwrap_or_else(|| "unknown".to_string());
+        let size_t = match arch {
+            Arch::X86 => 4,
+            Arch::X64 => 8,
+        };
+        let malloc_state = malloc_state_generator(&libc_version, arch);
+        let has_tcache = tcache_struct_generator(&libc_version, arch).is_some();
+
+        let mut symbols = libc_path
+            .as_deref()
+            .map(|p| LibcSymbols::load(p, config.debug_dir.as_deref()))
+            .unwrap_or_default();
+        // Calibration finds these in libcs that do not export them.
+        if let Some(p) = &profile {
+            symbols.global_max_fast = symbols.global_max_fast.or(p.global_max_fast_offset);
+            symbols.io_list_all = symbols.io_list_all.or(p.io_list_all_offset);
+        }
+        let tls_size = libc_path
+            .as_deref()
+            .and_then(|p| Elf::open(p).ok())
+            .and_then(|elf| elf.tls_size())
+            .unwrap_or(0x100);
+
+        let malloc_par = malloc_par_generator(&libc_version, arch);
+        let mp_offset = match (symbols.mp, profile.as_ref().and_then(|p| p.mp_offset)) {
+            (None, Some(offset)) => Some(offset),
+            _ => resolve::mp_offset(&proc, &symbols, libc_base, &malloc_par, size_t),
+        };
+
+        let main_arena_offset = match (config.main_arena_offset, symbols.main_arena, &profile) {
+            (0, None, Some(p)) => p.main_arena_offset,
+            (0, _, _) => resolve::main_arena_offset(
+                &proc,
+                &symbols,
+                libc_base,
+                &malloc_state,
+                size_t,
+            )
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "Failed to resolve main_arena offset",
+                )
+            })?,
+            (offset, _, _) => offset,
+        };
 
-        Ok(Self {
-            pid,
+        let mut hi = Self {
             proc,
-            arch,
-            size_t: 8,
-            libc_version,
-            tcache_enable: config.tcache_enable,
-            main_arena_offset: config.main_arena_offset,
-            libc_base,
-            heap_base,
-            libc_path,
-            ld_path,
-            exe_path,
-            malloc_state: malloc_state_generator("unknown"),
-            malloc_chunk: malloc_chunk_generator("unknown"),
-            tcache_struct: tcache_struct_generator("unknown"),
-        })
+            info: TargetInfo {
+                pid,
+                arch,
+                libc_version,
+                tcache_enable: has_tcache,
+                main_arena_offset,
+                mp_offset,
+                tcache_bins: profile.as_ref().and_then(|p| p.tcache_bins),
+                libc_path,
+                ld_path,
+                exe_path,
+                symbols,
+                tls_size,
+                warnings,
+            },
+            profile,
+            freeze: config.freeze,
+        };
+        hi.info.tcache_enable = match config.tcache_enable {
+            Some(enable) => enable && has_tcache,
+            None => hi.detect_tcache(),
+        };
+        Ok(hi)
+    }
+
+    /// Decides whether the target uses tcache, from cheapest to most
+    /// specific evidence. Anything inconclusive keeps tcache on.
+    fn detect_tcache(&self) -> bool {
+        // Pre-2.26 layouts have no tcache at all.
+        if !self.info.tcache_enable {
+            return false;
+        }
+        if self.proc.tunable("glibc.malloc.tcache_count").as_deref() == Some("0") {
+            return false;
+        }
+        if self.profile.as_ref().is_some_and(|p| !p.tcache_enable) {
+            return false;
+        }
+        let snap = Snapshot::new(self.info.clone(), MemoryImage::capture_memory(&self.proc));
+        // The profile stands in for `mp_` when it cannot be read.
+        let mp = snap.malloc_par();
+        let profile = self.profile.as_ref();
+        let bins = mp.as_ref().and_then(|mp| mp.tcache_bins());
+        let count = mp.as_ref().and_then(|mp| mp.tcache_count());
+        let bins = bins.or(profile.and_then(|p| p.tcache_bins));
+        let count = count.or(profile.and_then(|p| p.tcache_count));
+        if bins == Some(0) || count == Some(0) {
+            return false;
+        }
+        // tcache_init allocates the struct first, so a heap that starts
+        // with some other chunk comes from a libc built without tcache.
+        snap.tcache_chunk().is_some() || snap.heap_chunks().is_empty()
+    }
+
+    /// Reads the target's mappings, the memory malloc uses and thread
+    /// pointers once. Everything reported about the heap is derived from
+    /// the result.
+    /// With `freeze` set, a live target is stopped while this runs and
+    /// resumed afterwards, also when capturing fails.
+    pub fn capture(&self) -> io::Result<Snapshot> {
+        if !self.proc.is_core() && !self.proc.is_alive() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("process {} has exited", self.info.pid),
+            ));
+        }
+        let _freeze = match self.freeze {
+            Some(method) if !self.proc.is_core() => Some(Freeze::new(self.info.pid, method)?),
+            _ => None,
+        };
+        Ok(self.follow(MemoryImage::capture(&self.proc)))
+    }
+
+    /// Grows `image` from libc's data and `[heap]` to what malloc reaches
+    /// from there: thread arenas and their heaps, each thread's TLS, chunks
+    /// listed outside every heap, and finally the mmapped chunks. Stacks
+    /// and other data stay out.
+    fn follow(&self, mut image: MemoryImage) -> Snapshot {
+        for _ in 0..CAPTURE_ROUNDS {
+            let snap = Snapshot::new(self.info.clone(), image);
+            let missing = snap.missing_ranges();
+            image = snap.mem;
+            if missing.is_empty() {
+                break;
+            }
+            image.capture_ranges(&self.proc, &missing);
+        }
+        let snap = Snapshot::new(self.info.clone(), image);
+        let mmapped: Vec<Range<u64>> = snap
+            .find_mmapped(|reads| self.proc.read_many(reads))
+            .iter()
+            .map(|c| c.addr() - c.prev_size()..c.addr() + c.size())
+            .collect();
+        let mut image = snap.mem;
+        image.capture_ranges(&self.proc, &mmapped);
+        Snapshot::new(self.info.clone(), image)
+    }
+
+    #[allow(dead_code)]
+    pub fn record(&self) -> io::Result<HeapRecord> {
+        Ok(self.capture()?.record())
     }
 
     pub fn pid(&self) -> u64 {
-        self.pid
+        self.info.pid
     }
 
+    #[allow(dead_code)]
     pub fn arch(&self) -> Arch {
-        self.arch
+        self.info.arch
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.proc.is_alive()
     }
 
+    #[allow(dead_code)]
     pub fn libc_version(&self) -> &str {
-        &self.libc_version
+        &self.info.libc_version
     }
 
+    #[allow(dead_code)]
     pub fn libc_path(&self) -> Option<&str> {
-        self.libc_path.as_deref()
+        self.info.libc_path.as_deref()
     }
 
+    #[allow(dead_code)]
     pub fn ld_path(&self) -> Option<&str> {
-        self.ld_path.as_deref()
+        self.info.ld_path.as_deref()
     }
 
+    #[allow(dead_code)]
     pub fn exe_path(&self) -> &PathBuf {
-        &self.exe_path
+        &self.info.exe_path
+    }
+}
+
+/// The heap as it was at one capture: a `MemoryImage` plus the layouts
+/// needed to read it. Walkers never touch the target again, so everything
+/// derived from one snapshot is mutually consistent.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    info: TargetInfo,
+    mem: MemoryImage,
+    size_t: usize,
+    libc_base: u64,
+    heap_base: u64,
+    mapped: Vec<Range<u64>>,
+    malloc_state: CStructDef,
+    malloc_par: CStructDef,
+    malloc_chunk: CStructDef,
+    heap_info: CStructDef,
+    tcache_struct: Option<CStructDef>,
+}
+
+impl Snapshot {
+    pub fn new(info: TargetInfo, mem: MemoryImage) -> Self {
+        let (version, arch) = (info.libc_version.as_str(), info.arch);
+        let bases = mem.maps().bases();
+        let mut snap = Self {
+            size_t: match arch {
+                Arch::X86 => 4,
+                Arch::X64 => 8,
+            },
+            libc_base: first_base(&bases, "libc"),
+            heap_base: first_base(&bases, "heap"),
+            mapped: mem.maps().ranges().into_values().flatten().collect(),
+            malloc_state: malloc_state_generator(version, arch),
+            malloc_par: malloc_par_generator(version, arch),
+            malloc_chunk: malloc_chunk_generator(version, arch),
+            heap_info: heap_info_generator(version, arch),
+            tcache_struct: tcache_struct_generator(version, arch),
+            info,
+            mem,
+        };
+        // mp_.sbrk_base, when known, is where the main heap really starts.
+        if let Some(base) = snap.malloc_par().and_then(|mp| mp.sbrk_base())
+            && base != 0
+        {
+            snap.heap_base = base;
+        }
+        snap
+    }
+
+    pub fn info(&self) -> &TargetInfo {
+        &self.info
+    }
+
+    pub fn image(&self) -> &MemoryImage {
+        &self.mem
+    }
+
+    pub fn record(&self) -> HeapRecord {
+        HeapRecord::new(self)
+    }
+
+    pub fn libc_base(&self) -> u64 {
+        self.libc_base
+    }
+
+    pub fn heap_base(&self) -> u64 {
+        self.heap_base
+    }
+
+    /// Address of the `tcache_perthread_struct` chunk at the start of the
+    /// main heap (0x290, or 0x250 before 2.30), recognised by its size.
+    fn tcache_chunk(&self) -> Option<u64> {
+        let def = self.tcache_struct.as_ref()?;
+        let expected = self.request2size(def.size() as u64);
+        let heap_base = self.heap_base;
+        let size_t = self.size_t as u64;
+        // The first chunk may sit after a 2 * size_t alignment gap.
+        [heap_base, heap_base + 2 * size_t].into_iter().find(|&chunk| {
+            self.mem
+                .read(chunk + size_t, self.size_t)
+                .is_some_and(|mem| chunksize(uk64(&mem)) == expected)
+        })
+    }
+
+    fn request2size(&self, req: u64) -> u64 {
+        let size_t = self.size_t as u64;
+        let align_mask = self.malloc_alignment() - 1;
+        let min_size = (4 * size_t + align_mask) & !align_mask;
+        ((req + size_t + align_mask) & !align_mask).max(min_size)
+    }
+
+    /// MALLOC_ALIGNMENT of the target's libc.
+    pub fn malloc_alignment(&self) -> u64 {
+        malloc_alignment(&self.info.libc_version, self.info.arch) as u64
+    }
+
+    /// HEAP_MAX_SIZE: thread arena heaps are aligned to it, so
+    /// `heap_for_ptr` is a mask. Since 2.35 heaps on hugetlb pages span
+    /// four of them instead.
+    fn heap_max_size(&self) -> u64 {
+        match self.malloc_par().and_then(|mp| mp.hp_pagesize()) {
+            Some(page) if page != 0 => 4 * page,
+            _ => 2 * resolve::mmap_threshold_max(self.size_t),
+        }
     }
 
     pub fn ranges(&self) -> HashMap<String, Vec<Range<u64>>> {
-        self.proc.ranges()
+        self.mem.maps().ranges()
     }
 
     pub fn bases(&self) -> HashMap<String, Vec<u64>> {
-        self.proc.bases()
+        self.mem.maps().bases()
     }
 
     pub fn heapmem(&self) -> Option<Vec<u8>> {
-        let ranges = self.proc.ranges();
-        let heap_ranges = ranges.get("heap")?;
-        let first = heap_ranges.first()?;
-        let size_u64 = first.end.saturating_sub(first.start);
+        let base = self.heap_base;
+        let ranges = self.mem.maps().ranges();
+        let heap = ranges.get("heap")?.iter().find(|r| r.contains(&base))?;
+        let size_u64 = heap.end.saturating_sub(base);
         let size = usize::try_from(size_u64).ok()?;
-        self.proc.read(first.start, size)
+        self.mem.read(base, size)
+    }
+
+    pub fn malloc_par(&self) -> Option<MallocPar> {
+        let addr = self.libc_base + self.info.mp_offset?;
+        let mem = self.mem.read(addr, self.malloc_par.size())?;
+        Some(MallocPar::new(&self.malloc_par, mem, addr))
+    }
+
+    /// Number of tcache bins in use: `mp_.tcache_bins`, the profile's value
+    /// when `mp_` could not be read, or else the full `entries` array.
+    pub fn tcache_bins(&self) -> usize {
+        let max = self
+            .tcache_struct
+            .as_ref()
+            .and_then(|def| def.count_of("entries"))
+            .unwrap_or(0);
+        self.malloc_par()
+            .and_then(|mp| mp.tcache_bins())
+            .or(self.info.tcache_bins)
+            .map_or(max, |n| (n as usize).min(max))
     }
 
     pub fn arenamem(&self) -> Option<Vec<u8>> {
         let arena_size = self.malloc_state.size();
-        let arena_addr = self.libc_base() + self.main_arena_offset;
-        self.proc.read(arena_addr, arena_size)
+        let arena_addr = self.libc_base + self.info.main_arena_offset;
+        self.mem.read(arena_addr, arena_size)
     }
 
     pub fn main_arena(&self) -> Option<MallocState> {
-        let arena_addr = self.libc_base() + self.main_arena_offset;
+        let arena_addr = self.libc_base + self.info.main_arena_offset;
         let mem = self.arenamem()?;
         Some(MallocState::new(&self.malloc_state, mem, arena_addr))
     }
 
-    pub fn tcache(&self) -> Option<Tcache> {
-        if !self.tcache_enable {
-            return None;
-        }
+    fn arena_at(&self, addr: u64) -> Option<MallocState> {
+        let mem = self.mem.read(addr, self.malloc_state.size())?;
+        Some(MallocState::new(&self.malloc_state, mem, addr))
+    }
 
-        let heap_base = self.heap_base();
-        let testmem = self.proc.read(heap_base + self.size_t as u64, self.size_t)?;
-        let testval = uk64(testmem);
-        let base_addr = if testval == 0 {
-            heap_base + 4 * self.size_t as u64
-        } else {
-            heap_base + 2 * self.size_t as u64
-        };
+    /// Every arena on the `next` ring, `main_arena` first.
+    pub fn arenas(&self) -> Vec<MallocState> {
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let mut cur = self.main_arena();
+        while let Some(arena) = cur {
+            if !seen.insert(arena.addr()) {
+                break;
+            }
+            let next = arena.next();
+            result.push(arena);
+            cur = if next == 0 { None } else { self.arena_at(next) };
+        }
+        result
+    }
 
-        let mem = self.proc.read(base_addr, self.tcache_struct.size())?;
-        Some(Tcache::new(&self.tcache_struct, mem, base_addr))
+    /// Heaps of a thread arena from the first one mapped to the one holding
+    /// `top`. Empty for `main_arena`, which lives in `[heap]`.
+    pub fn heap_infos(&self, arena: &MallocState) -> Vec<HeapInfo> {
+        let mut result = Vec::new();
+        if arena.addr() == self.libc_base + self.info.main_arena_offset {
+            return result;
+        }
+        let mut seen = HashSet::new();
+        let mut addr = arena.top() & !(self.heap_max_size() - 1);
+        while addr != 0 && seen.insert(addr) {
+            let mem = match self.mem.read(addr, self.heap_info.size()) {
+                Some(m) => m,
+                None => break,
+            };
+            let heap = HeapInfo::new(&self.heap_info, mem, addr);
+            if heap.ar_ptr() != arena.addr() {
+                break;
+            }
+            addr = heap.prev();
+            result.push(heap);
+        }
+        result.reverse();
+        result
+    }
+
+    /// Chunks of a thread arena, heap by heap. The first heap also holds
+    /// the `malloc_state` right after its `heap_info`.
+    pub fn arena_chunks(&self, arena: &MallocState) -> Vec<MallocChunk> {
+        let align_mask = self.malloc_alignment() - 1;
+        let header = 2 * self.size_t as u64;
+        let mut result = Vec::new();
+        for heap in self.heap_infos(arena) {
+            let mut start = heap.addr() + self.heap_info.size() as u64;
+            if heap.prev() == 0 {
+                start += self.malloc_state.size() as u64;
+            }
+            // The user pointer, not the header, is what gets aligned.
+            start = ((start + header + align_mask) & !align_mask) - header;
+            let end = heap.addr() + heap.size().min(self.heap_max_size());
+            let mem = match usize::try_from(end.saturating_sub(start))
+                .ok()
+                .and_then(|len| self.mem.read(start, len))
+            {
+                Some(m) => m,
+                None => continue,
+            };
+            result.extend(self.walk_chunks(&mem, start));
+        }
+        result
+    }
+
+    /// Memory the chunks of `arena` live in: the `[heap]` mapping from
+    /// `heap_base` for `main_arena`, the `heap_info` heaps otherwise.
+    pub fn arena_heaps(&self, arena: &MallocState) -> Vec<Range<u64>> {
+        if self.main_arena().is_some_and(|main| main.addr() == arena.addr()) {
+            let base = self.heap_base();
+            return self
+                .ranges()
+                .get("heap")
+                .and_then(|r| r.iter().find(|r| r.contains(&base)).cloned())
+                .map(|r| base..r.end)
+                .into_iter()
+                .collect();
+        }
+        self.heap_infos(arena)
+            .iter()
+            .map(|h| h.addr()..h.addr() + h.size())
+            .collect()
+    }
+
+    pub fn arena_record(&self, arena: &MallocState) -> ArenaRecord {
+        ArenaRecord {
+            addr: arena.addr(),
+            state: arena.clone(),
+            heaps: self.arena_heaps(arena),
+            heap_chunks: self.arena_chunks(arena),
+            fastbins: self.arena_fastbins(arena),
+            unsortedbins: self.arena_unsortedbins(arena),
+            smallbins: self.arena_smallbins(arena),
+            largebins: self.arena_largebins(arena),
+        }
+    }
+
+    /// Chunks malloc served straight from mmap. Each one starts its own
+    /// anonymous mapping, so only page starts outside the arenas' heaps and
+    /// libc's data are checked for an IS_MMAPPED header.
+    pub fn mmapped_chunks(&self) -> Vec<MallocChunk> {
+        self.find_mmapped(|reads| self.mem.read_many(reads))
+    }
+
+    /// `mmapped_chunks` with page headers read through `read_many`, so
+    /// capturing can find them before their pages are in the image.
+    fn find_mmapped<F>(&self, read_many: F) -> Vec<MallocChunk>
+    where
+        F: Fn(&[(u64, usize)]) -> Vec<Option<Vec<u8>>>,
+    {
+        let size_t = self.size_t as u64;
+        let mut skip: Vec<Range<u64>> = self.mem.maps().libc_data_ranges();
+        for arena in self.arenas().iter().skip(1) {
+            skip.extend(
+                self.heap_infos(arena)
+                    .iter()
+                    .map(|h| h.addr()..h.addr() + self.heap_max_size()),
+            );
+        }
+        // When MALLOC_ALIGNMENT exceeds the header (i386), the chunk is
+        // moved up and prev_size records the correction.
+        let correction = (self.malloc_alignment() - 2 * size_t) % self.malloc_alignment();
+
+        let mut result = Vec::new();
+        for range in self.mem.maps().anon_ranges() {
+            // One batched read of every page header in the range.
+            let pages: Vec<u64> = (range.start..range.end)
+                .step_by(PAGE_SIZE as usize)
+                .filter(|&page| {
+                    page + correction + 4 * size_t <= range.end
+                        && !skip.iter().any(|r| r.contains(&page))
+                })
+                .collect();
+            let reads: Vec<(u64, usize)> = pages
+                .iter()
+                .map(|&page| (page + correction, 4 * self.size_t))
+                .collect();
+            let headers: HashMap<u64, Vec<u8>> = pages
+                .into_iter()
+                .zip(read_many(&reads))
+                .filter_map(|(page, mem)| Some((page, mem?)))
+                .collect();
+
+            let mut addr = range.start;
+            while addr < range.end {
+                let Some(mem) = headers.get(&addr) else {
+                    addr += PAGE_SIZE;
+                    continue;
+                };
+                let chunk = MallocChunk::new(&self.malloc_chunk, mem.clone(), addr + correction);
+                let mapped_size = chunk.size() + correction;
+                let valid = chunk.is_mmapped()
+                    && chunk.prev_size() == correction
+                    && mapped_size >= PAGE_SIZE
+                    && mapped_size.is_multiple_of(PAGE_SIZE)
+                    && addr + mapped_size <= range.end;
+                if !valid {
+                    addr += PAGE_SIZE;
+                    continue;
+                }
+                result.push(chunk);
+                addr += mapped_size;
+            }
+        }
+        result
+    }
+
+    /// Memory the walkers reach that is not in the image yet, widened to
+    /// pages and clipped to the writable mapping each one starts in.
+    fn missing_ranges(&self) -> Vec<Range<u64>> {
+        let size_t = self.size_t as u64;
+        let word = |addr: u64| self.mem.read(addr, self.size_t).map(|m| uk64(&m));
+        let mut wanted: Vec<Range<u64>> = Vec::new();
+
+        let arenas = self.arenas();
+        for arena in &arenas {
+            if arena.next() != 0 {
+                wanted.push(arena.next()..arena.next() + self.malloc_state.size() as u64);
+            }
+            if arena.addr() == self.libc_base + self.info.main_arena_offset {
+                continue;
+            }
+            // The heap holding top, then each heap before it.
+            let first = arena.top() & !(self.heap_max_size() - 1);
+            wanted.push(first..first + self.heap_info.size() as u64);
+            for heap in self.heap_infos(arena) {
+                wanted.push(heap.addr()..heap.addr() + heap.size());
+                if heap.prev() != 0 {
+                    wanted.push(heap.prev()..heap.prev() + self.heap_info.size() as u64);
+                }
+            }
+        }
+
+        // tcbhead_t, the DTV and the TLS blocks it points at.
+        for thread in self.mem.threads() {
+            let fs_base = thread.thread_pointer;
+            wanted.push(fs_base..fs_base + 2 * size_t);
+            if let Some(dtv) = word(fs_base + size_t) {
+                wanted.push(dtv.saturating_sub(2 * size_t)..dtv);
+            }
+            if let Some((dtv, slots)) = self.dtv(fs_base) {
+                wanted.push(dtv..dtv + 2 * size_t * (slots + 1));
+            }
+            for block in self.tls_blocks(fs_base).unwrap_or_default() {
+                wanted.push(block..block + self.info.tls_size.max(size_t));
+            }
+        }
+
+        // Chunks the bins and tcaches point at outside every heap, e.g.
+        // fake chunks on the stack.
+        let reach = self.malloc_chunk.size() as u64;
+        wanted.extend(
+            self.listed_targets(&arenas)
+                .into_iter()
+                .filter(|&addr| addr != 0)
+                .map(|addr| addr.saturating_sub(2 * size_t)..addr + reach),
+        );
+
+        let mut missing: Vec<Range<u64>> = Vec::new();
+        for range in wanted {
+            let Some(map) = self
+                .mem
+                .maps()
+                .iter()
+                .find(|m| m.perm().starts_with("rw") && m.range().contains(&range.start))
+            else {
+                continue;
+            };
+            let start = range.start & !(PAGE_SIZE - 1);
+            let end = range.end.next_multiple_of(PAGE_SIZE).min(map.range().end);
+            let Ok(size) = usize::try_from(end - start) else {
+                continue;
+            };
+            if self.mem.read(start, size).is_none() && !missing.contains(&(start..end)) {
+                missing.push(start..end);
+            }
+        }
+        missing
+    }
+
+    /// Every address a bin, fastbin or tcache of `arenas` and the threads
+    /// points at: list heads and the `fd`/`bk` of each chunk on them.
+    fn listed_targets(&self, arenas: &[MallocState]) -> Vec<u64> {
+        let mut result = Vec::new();
+        let mut tcaches: Vec<Tcache> = self.tcache().into_iter().collect();
+        tcaches.extend(self.threads().into_iter().filter_map(|t| t.tcache));
+        for tcache in &tcaches {
+            result.extend(tcache.entries().unwrap_or_default());
+            for chain in self.thread_tcache_chains(tcache).values() {
+                result.extend(chain.links.iter().map(|l| l.decoded));
+            }
+        }
+        for arena in arenas {
+            result.extend(arena.fastbins().unwrap_or_default());
+            for chain in self.arena_fastbin_chains(arena).values() {
+                result.extend(chain.links.iter().map(|l| l.decoded));
+            }
+            result.extend((UNSORTED_BIN..LARGEBINS.end).filter_map(|i| arena.bin_bk(i)));
+            let chunks = self
+                .arena_unsortedbins(arena)
+                .into_iter()
+                .chain(self.arena_smallbins(arena).into_values().flatten())
+                .chain(self.arena_largebins(arena).into_values().flatten());
+            for chunk in chunks {
+                result.extend([chunk.fd(), chunk.bk()]);
+            }
+        }
+        result
+    }
+
+    pub fn tcache(&self) -> Option<Tcache> {
+        if !self.info.tcache_enable {
+            return None;
+        }
+
+        let base_addr = self.tcache_chunk()? + 2 * self.size_t as u64;
+        let tcache_struct = self.tcache_struct.as_ref()?;
+        let mem = self.mem.read(base_addr, tcache_struct.size())?;
+        Some(Tcache::new(tcache_struct, mem, base_addr))
+    }
+
+    /// Every thread of the target with the `tcache` and `thread_arena`
+    /// values of its own TLS. Threads whose registers could not be read
+    /// at capture time are missing.
+    pub fn threads(&self) -> Vec<ThreadRecord> {
+        let arenas: Vec<u64> = self.arenas().iter().map(|a| a.addr()).collect();
+        self.mem
+            .threads()
+            .iter()
+            .map(|thread| {
+                let fs_base = thread.thread_pointer;
+                let (tcache_addr, arena) = self
+                    .thread_tls(fs_base, self.info.tls_size, &arenas)
+                    .unwrap_or((0, 0));
+                let tcache = match (&self.tcache_struct, tcache_addr) {
+                    (Some(def), addr) if addr != 0 && self.info.tcache_enable => self
+                        .mem
+                        .read(addr, def.size())
+                        .map(|mem| Tcache::new(def, mem, addr)),
+                    _ => None,
+                };
+                let tcache_chunks = tcache
+                    .as_ref()
+                    .map(|t| self.thread_tcache_chunks(t))
+                    .unwrap_or_default();
+                ThreadRecord {
+                    tid: thread.tid,
+                    fs_base,
+                    arena,
+                    tcache,
+                    tcache_chunks,
+                }
+            })
+            .collect()
+    }
+
+    /// `(tcache, thread_arena)` in the thread whose TCB is at `fs_base`.
+    /// libc's TLS block is found through the DTV: it is the module block
+    /// where both variables hold plausible values.
+    fn thread_tls(&self, fs_base: u64, tls_size: u64, arenas: &[u64]) -> Option<(u64, u64)> {
+        self.tls_blocks(fs_base)?
+            .into_iter()
+            .filter_map(|block| self.tls_candidate(block, tls_size, arenas))
+            .max_by_key(|&(tcache, arena)| (arena != 0) as u8 + (tcache != 0) as u8)
+    }
+
+    /// The `dtv` of the thread whose TCB is at `fs_base` and its slot count.
+    fn dtv(&self, fs_base: u64) -> Option<(u64, u64)> {
+        let size_t = self.size_t as u64;
+        let word = |addr: u64| self.mem.read(addr, self.size_t).map(|m| uk64(&m));
+        // tcbhead_t.dtv points at dtv[1]; dtv[-1] holds the slot count.
+        let dtv = word(fs_base + size_t)?;
+        let slots = word(dtv.checked_sub(2 * size_t)?)?.min(64);
+        Some((dtv, slots))
+    }
+
+    /// Module TLS blocks the DTV of the thread at `fs_base` has allocated.
+    fn tls_blocks(&self, fs_base: u64) -> Option<Vec<u64>> {
+        let size_t = self.size_t as u64;
+        let word = |addr: u64| self.mem.read(addr, self.size_t).map(|m| uk64(&m));
+        let (dtv, slots) = self.dtv(fs_base)?;
+        // TLS_DTV_UNALLOCATED is (void *) -1.
+        let unallocated = u64::MAX >> (64 - 8 * size_t);
+        Some(
+            (1..=slots)
+                .filter_map(|modid| word(dtv + modid * 2 * size_t))
+                .filter(|&block| block != 0 && block != unallocated)
+                .collect(),
+        )
+    }
+
+    fn tls_candidate(&self, block: u64, tls_size: u64, arenas: &[u64]) -> Option<(u64, u64)> {
+        let word = |addr: u64| self.mem.read(addr, self.size_t).map(|m| uk64(&m));
+        if let (Some(tcache_off), Some(arena_off)) =
+            (self.info.symbols.tcache, self.info.symbols.thread_arena)
+        {
+            let arena = word(block + arena_off)?;
+            if arena != 0 && !arenas.contains(&arena) {
+                return None;
+            }
+            let tcache = word(block + tcache_off).filter(|&t| self.is_tcache_struct(t));
+            return Some((tcache.unwrap_or(0), arena));
+        }
+
+        // Without symbols, take the first words that point at an arena
+        // and at a tcache_perthread_struct.
+        let mem = self.mem.read(block, usize::try_from(tls_size).ok()?)?;
+        let words: Vec<u64> = mem.chunks_exact(self.size_t).map(uk64).collect();
+        let arena = words.iter().copied().find(|w| arenas.contains(w));
+        let tcache = words.iter().copied().find(|&w| self.is_tcache_struct(w));
+        Some((tcache.unwrap_or(0), arena.unwrap_or(0)))
+    }
+
+    /// Whether `addr` is the user pointer of a `tcache_perthread_struct` chunk.
+    fn is_tcache_struct(&self, addr: u64) -> bool {
+        let def = match &self.tcache_struct {
+            Some(d) => d,
+            None => return false,
+        };
+        let size_t = self.size_t as u64;
+        addr > size_t
+            && addr & (self.malloc_alignment() - 1) == 0
+            && self
+                .mem
+                .read(addr - size_t, self.size_t)
+                .is_some_and(|mem| chunksize(uk64(&mem)) == self.request2size(def.size() as u64))
+    }
+
+    /// The chunk header at `addr`. Only the header is required to be
+    /// captured; the list pointers read as 0 otherwise.
+    pub fn chunk_at(&self, addr: u64) -> Option<MallocChunk> {
+        let mem = self
+            .mem
+            .read(addr, self.malloc_chunk.size())
+            .or_else(|| self.mem.read(addr, 2 * self.size_t))?;
+        Some(MallocChunk::new(&self.malloc_chunk, mem, addr))
+    }
+
+    /// The chunk physically after `chunk`.
+    pub fn next_chunk(&self, chunk: &MallocChunk) -> Option<MallocChunk> {
+        if chunk.is_mmapped() {
+            return None;
+        }
+        self.chunk_at(chunk.next_addr())
+    }
+
+    /// The chunk physically before `chunk`, when its PREV_INUSE bit says it
+    /// is free and `prev_size` is meaningful.
+    pub fn prev_chunk(&self, chunk: &MallocChunk) -> Option<MallocChunk> {
+        self.chunk_at(chunk.prev_addr()?)
     }
 
     pub fn heap_chunks(&self) -> Vec<MallocChunk> {
@@ -236,6 +1261,7 @@ impl HeapInspector {
             Some(m) => m,
             None => return Vec::new(),
         };
+        let heap_base = self.heap_base;
         let mut cur_pos = 0usize;
         let size_t = self.size_t;
 
@@ -243,31 +1269,41 @@ impl HeapInspector {
             return Vec::new();
         }
 
-        let first_chunk_size = uk64(&heap_mem[size_t..size_t * 2]) & !0b111;
+        let first_chunk_size = chunksize(uk64(&heap_mem[size_t..size_t * 2]));
         if first_chunk_size == 0 {
             cur_pos += 2 * size_t;
         }
+        self.walk_chunks(&heap_mem[cur_pos..], heap_base + cur_pos as u64)
+    }
 
+    /// Walks physically adjacent chunks in `heap_mem`, which starts with a
+    /// chunk header at `heap_base`, until a size stops making sense.
+    fn walk_chunks(&self, heap_mem: &[u8], heap_base: u64) -> Vec<MallocChunk> {
+        let size_t = self.size_t;
+        let mut cur_pos = 0usize;
         let mut result = Vec::new();
         while cur_pos + size_t * 2 <= heap_mem.len() {
             let size_slice = &heap_mem[cur_pos + size_t..cur_pos + size_t * 2];
-            let cur_block_size = uk64(size_slice) & !0b111;
+            let cur_block_size = chunksize(uk64(size_slice));
             if cur_block_size == 0 {
                 break;
             }
-            let end = match usize::try_from(cur_block_size) {
-                Ok(sz) => cur_pos + sz,
-                Err(_) => break,
+            let end = match usize::try_from(cur_block_size)
+                .ok()
+                .and_then(|sz| cur_pos.checked_add(sz))
+            {
+                Some(end) => end,
+                None => break,
             };
             if end > heap_mem.len() {
                 break;
             }
 
             let memblock = heap_mem[cur_pos..end].to_vec();
-            let addr = self.heap_base() + cur_pos as u64;
+            let addr = heap_base + cur_pos as u64;
             result.push(MallocChunk::new(&self.malloc_chunk, memblock, addr));
 
-            let align_mask = 0b1111usize;
+            let align_mask = self.malloc_alignment() as usize - 1;
             let next = (cur_pos + cur_block_size as usize) & !align_mask;
             if cur_block_size < (2 * size_t) as u64 || next <= cur_pos {
                 break;
@@ -278,163 +1314,195 @@ impl HeapInspector {
     }
 
     pub fn tcache_chunks(&self) -> HashMap<usize, Vec<MallocChunk>> {
-        if !self.tcache_enable {
-            return HashMap::new();
-        }
+        self.tcache()
+            .map(|tcache| self.thread_tcache_chunks(&tcache))
+            .unwrap_or_default()
+    }
 
-        let mut result: HashMap<usize, Vec<MallocChunk>> = HashMap::new();
-        let tcache = match self.tcache() {
-            Some(t) => t,
-            None => return result,
-        };
-        let entries = match tcache.entries() {
-            Some(e) => e,
-            None => return result,
-        };
+    pub fn tcache_chains(&self) -> HashMap<usize, Chain> {
+        self.tcache()
+            .map(|tcache| self.thread_tcache_chains(&tcache))
+            .unwrap_or_default()
+    }
 
-        for (index, entry_ptr) in entries.into_iter().enumerate() {
-            let mut ptr = entry_ptr;
-            let mut lst = Vec::new();
-            let mut traversed = Vec::new();
-            while ptr != 0 {
-                let addr = ptr.saturating_sub(2 * self.size_t as u64);
-                let mem = match self.proc.read(addr, 4 * self.size_t) {
-                    Some(m) => m,
-                    None => break,
-                };
-                let chunk = MallocChunk::new(&self.malloc_chunk, mem, addr);
-                let next = chunk.fd();
-                lst.push(chunk);
-                if traversed.contains(&ptr) {
-                    break;
-                }
-                traversed.push(ptr);
-                ptr = next;
-            }
-            if !lst.is_empty() {
-                result.insert(index, lst);
-            }
-        }
-        result
+    pub fn thread_tcache_chunks(&self, tcache: &Tcache) -> HashMap<usize, Vec<MallocChunk>> {
+        self.tcache_walks(tcache)
+            .into_iter()
+            .map(|(index, (chunks, _))| (index, chunks))
+            .collect()
     }
 
-    pub fn tcache_chains(&self) -> HashMap<usize, Chain> {
-        if !self.tcache_enable {
-            return HashMap::new();
-        }
+    pub fn thread_tcache_chains(&self, tcache: &Tcache) -> HashMap<usize, Chain> {
+        self.tcache_walks(tcache)
+            .into_iter()
+            .map(|(index, (_, chain))| (index, chain))
+            .collect()
+    }
 
-        let mut result: HashMap<usize, Chain> = HashMap::new();
-        let tcache = match self.tcache() {
-            Some(t) => t,
-            None => return result,
-        };
-        let entries = match tcache.entries() {
-            Some(e) => e,
-            None => return result,
-        };
+    pub fn fastbins(&self) -> HashMap<usize, Vec<MallocChunk>> {
+        self.main_arena()
+            .map(|arena| self.arena_fastbins(&arena))
+            .unwrap_or_default()
+    }
 
-        for (index, entry_ptr) in entries.into_iter().enumerate() {
-            let mut addrs = Vec::new();
-            let mut seen = HashSet::new();
-            let mut ptr = entry_ptr;
-            let mut cycle = false;
+    pub fn fastbin_chains(&self) -> HashMap<usize, Chain> {
+        self.main_arena()
+            .map(|arena| self.arena_fastbin_chains(&arena))
+            .unwrap_or_default()
+    }
 
-            while ptr != 0 {
-                if !seen.insert(ptr) {
-                    cycle = true;
-                    break;
-                }
-                let addr = ptr.saturating_sub(2 * self.size_t as u64);
-                let mem = match self.proc.read(addr, 4 * self.size_t) {
-                    Some(m) => m,
-                    None => break,
-                };
-                let chunk = MallocChunk::new(&self.malloc_chunk, mem, addr);
-                addrs.push(chunk.addr());
-                ptr = chunk.fd();
-            }
+    pub fn arena_fastbins(&self, arena: &MallocState) -> HashMap<usize, Vec<MallocChunk>> {
+        self.fastbin_walks(arena)
+            .into_iter()
+            .map(|(index, (chunks, _))| (index, chunks))
+            .collect()
+    }
 
-            if !addrs.is_empty() {
-                result.insert(index, Chain { addrs, cycle });
-            }
+    pub fn arena_fastbin_chains(&self, arena: &MallocState) -> HashMap<usize, Chain> {
+        self.fastbin_walks(arena)
+            .into_iter()
+            .map(|(index, (_, chain))| (index, chain))
+            .collect()
+    }
+
+    /// Whether `tcache_entry.next` and fastbin `fd` are mangled with
+    /// PROTECT_PTR (glibc >= 2.32). An unknown version is treated as mangled,
+    /// since that is what every supported glibc does.
+    pub fn safe_linking(&self) -> bool {
+        match parse_version(&self.info.libc_version) {
+            Some(v) => v >= (2, 32),
+            None => true,
         }
+    }
 
-        result
+    /// REVEAL_PTR: `pos` is the address the pointer was stored at.
+    fn reveal_ptr(&self, pos: u64, raw: u64) -> u64 {
+        if self.safe_linking() {
+            (pos >> 12) ^ raw
+        } else {
+            raw
+        }
     }
 
-    pub fn fastbins(&self) -> HashMap<usize, Vec<MallocChunk>> {
+    fn tcache_walks(&self, tcache: &Tcache) -> HashMap<usize, (Vec<MallocChunk>, Chain)> {
         let mut result = HashMap::new();
-        let arena = match self.main_arena() {
-            Some(a) => a,
-            None => return result,
-        };
-        let fastbins = match arena.fastbins() {
-            Some(f) => f,
+        let entries = match tcache.entries() {
+            Some(e) => e,
             None => return result,
         };
 
-        for (index, fastbin_head) in fastbins.into_iter().enumerate() {
-            let mut fastbin_ptr = fastbin_head;
-            let mut lst = Vec::new();
-            let mut traversed = Vec::new();
-            while fastbin_ptr != 0 {
-                let mem = match self.proc.read(fastbin_ptr, 4 * self.size_t) {
-                    Some(m) => m,
-                    None => break,
-                };
-                let chunk =
-                    MallocChunk::new(&self.malloc_chunk, mem, fastbin_ptr);
-                let fd = chunk.fd();
-                lst.push(chunk);
-                if traversed.contains(&fastbin_ptr) {
-                    break;
-                }
-                traversed.push(fastbin_ptr);
-                fastbin_ptr = fd;
-            }
-            if !lst.is_empty() {
-                result.insert(index, lst);
+        // tcache entries point at the user data, two size_t past the chunk.
+        let entry_offset = 2 * self.size_t as u64;
+        let heads: Vec<u64> = entries.into_iter().take(self.tcache_bins()).collect();
+        let walks = self.walk_fd_lists(&heads, entry_offset);
+        for (index, (chunks, chain)) in walks.into_iter().enumerate() {
+            if !chunks.is_empty() {
+                result.insert(index, (chunks, chain));
             }
         }
         result
     }
 
-    pub fn fastbin_chains(&self) -> HashMap<usize, Chain> {
+    fn fastbin_walks(&self, arena: &MallocState) -> HashMap<usize, (Vec<MallocChunk>, Chain)> {
         let mut result = HashMap::new();
-        let arena = match self.main_arena() {
-            Some(a) => a,
-            None => return result,
-        };
         let fastbins = match arena.fastbins() {
             Some(f) => f,
             None => return result,
         };
 
-        for (index, fastbin_head) in fastbins.into_iter().enumerate() {
-            let mut addrs = Vec::new();
-            let mut seen = HashSet::new();
-            let mut ptr = fastbin_head;
-            let mut cycle = false;
+        for (index, (chunks, chain)) in self.walk_fd_lists(&fastbins, 0).into_iter().enumerate() {
+            if !chunks.is_empty() {
+                result.insert(index, (chunks, chain));
+            }
+        }
+        result
+    }
 
-            while ptr != 0 {
-                if !seen.insert(ptr) {
-                    cycle = true;
-                    break;
-                }
-                let mem = match self.proc.read(ptr, 4 * self.size_t) {
-                    Some(m) => m,
-                    None => break,
-                };
-                let chunk = MallocChunk::new(&self.malloc_chunk, mem, ptr);
-                addrs.push(chunk.addr());
-                ptr = chunk.fd();
+    /// Follows singly linked free lists (tcache or fastbins) from `heads`.
+    /// `entry_offset` is the distance between the chunk and the address the
+    /// list pointers refer to.
+    fn walk_fd_lists(&self, heads: &[u64], entry_offset: u64) -> Vec<(Vec<MallocChunk>, Chain)> {
+        let mut walks: Vec<ListWalk> = heads.iter().map(|_| ListWalk::default()).collect();
+        let starts = heads
+            .iter()
+            .zip(walks.iter_mut())
+            .map(|(&head, walk)| {
+                (head != 0 && walk.seen.insert(head)).then(|| head.saturating_sub(entry_offset))
+            })
+            .collect();
+
+        self.walk_lists(starts, 4 * self.size_t, |i, chunk| {
+            let walk = &mut walks[i];
+            let fd_addr = chunk.addrof("fd").unwrap_or(chunk.addr());
+            let link = self.make_link(
+                chunk.fd(),
+                self.reveal_ptr(fd_addr, chunk.fd()),
+                entry_offset,
+            );
+            let next = link.decoded;
+            let broken = link.misaligned || link.unmapped;
+            walk.chunks.push(chunk);
+            walk.links.push(link);
+            if broken || next == 0 {
+                return None;
+            }
+            if !walk.seen.insert(next) {
+                walk.cycle = true;
+                return None;
             }
+            Some(next.saturating_sub(entry_offset))
+        });
+        walks.into_iter().map(ListWalk::finish).collect()
+    }
 
-            if !addrs.is_empty() {
-                result.insert(index, Chain { addrs, cycle });
+    /// Walks several linked lists in lockstep so that each step costs one
+    /// batched read. `starts[i]` is the first node of list `i`; `step`
+    /// receives every node read and returns the next one, if any.
+    fn walk_lists<F>(&self, starts: Vec<Option<u64>>, len: usize, mut step: F)
+    where
+        F: FnMut(usize, MallocChunk) -> Option<u64>,
+    {
+        let mut cur = starts;
+        loop {
+            let active: Vec<(usize, u64)> = cur
+                .iter()
+                .enumerate()
+                .filter_map(|(i, addr)| addr.map(|a| (i, a)))
+                .collect();
+            if active.is_empty() {
+                break;
+            }
+            let reads: Vec<(u64, usize)> = active.iter().map(|&(_, addr)| (addr, len)).collect();
+            for ((i, addr), mem) in active.into_iter().zip(self.mem.read_many(&reads)) {
+                cur[i] = mem.and_then(|mem| {
+                    step(i, MallocChunk::new(&self.malloc_chunk, mem, addr))
+                });
             }
         }
-        result
+    }
+
+    /// Builds a link to `decoded`, which points `entry_offset` bytes into
+    /// its chunk. Alignment is checked on the user pointer, as glibc's
+    /// `misaligned_chunk` does, since chunk headers need not be aligned.
+    fn make_link(&self, raw: u64, decoded: u64, entry_offset: u64) -> Link {
+        let align_mask = self.malloc_alignment() - 1;
+        let mem = decoded
+            .wrapping_sub(entry_offset)
+            .wrapping_add(2 * self.size_t as u64);
+        let (misaligned, unmapped) = if decoded == 0 {
+            (false, false)
+        } else {
+            (
+                mem & align_mask != 0,
+                !self.mapped.iter().any(|r| r.contains(&decoded)),
+            )
+        };
+        Link {
+            raw,
+            decoded,
+            misaligned,
+            unmapped,
+        }
     }
 
     pub fn bins(
@@ -443,46 +1511,22 @@ impl HeapInspector {
         end: usize,
         chunk_size: usize,
     ) -> HashMap<usize, Vec<MallocChunk>> {
-        let mut result = HashMap::new();
-        let arena = match self.main_arena() {
-            Some(a) => a,
-            None => return result,
-        };
-
-        for index in start..end {
-            let head_addr = match arena.addrof(&format!("bins[{}]", index * 2)) {
-                Some(a) => a.saturating_sub(2 * self.size_t as u64),
-                None => continue,
-            };
-            let mut chunk_ptr = head_addr;
-            let mut lst = Vec::new();
-            let mut traversed = Vec::new();
-
-            let mem = match self.proc.read(chunk_ptr, chunk_size) {
-                Some(m) => m,
-                None => continue,
-            };
-            let mut chunk = MallocChunk::new(&self.malloc_chunk, mem, chunk_ptr);
+        self.main_arena()
+            .map(|arena| self.arena_bins(&arena, start, end, chunk_size))
+            .unwrap_or_default()
+    }
 
-            while chunk.bk() != head_addr {
-                chunk_ptr = chunk.bk();
-                let mem = match self.proc.read(chunk_ptr, chunk_size) {
-                    Some(m) => m,
-                    None => break,
-                };
-                chunk = MallocChunk::new(&self.malloc_chunk, mem, chunk_ptr);
-                let bk = chunk.bk();
-                lst.push(chunk.clone());
-                if traversed.contains(&bk) {
-                    break;
-                }
-                traversed.push(bk);
-            }
-            if !lst.is_empty() {
-                result.insert(index, lst);
-            }
-        }
-        result
+    pub fn arena_bins(
+        &self,
+        arena: &MallocState,
+        start: usize,
+        end: usize,
+        chunk_size: usize,
+    ) -> HashMap<usize, Vec<MallocChunk>> {
+        self.bin_walks(arena, start, end, chunk_size)
+            .into_iter()
+            .map(|(index, (chunks, _))| (index, chunks))
+            .collect()
     }
 
     pub fn bin_chains(
@@ -491,92 +1535,276 @@ impl HeapInspector {
         end: usize,
         chunk_size: usize,
     ) -> HashMap<usize, Chain> {
-        let mut result = HashMap::new();
-        let arena = match self.main_arena() {
-            Some(a) => a,
-            None => return result,
-        };
+        self.main_arena()
+            .map(|arena| self.arena_bin_chains(&arena, start, end, chunk_size))
+            .unwrap_or_default()
+    }
 
-        for index in start..end {
-            let head_addr = match arena.addrof(&format!("bins[{}]", index * 2)) {
-                Some(a) => a.saturating_sub(2 * self.size_t as u64),
-                None => continue,
-            };
-            let mut addrs = Vec::new();
-            let mut seen = HashSet::new();
-            let mut cycle = false;
+    pub fn arena_bin_chains(
+        &self,
+        arena: &MallocState,
+        start: usize,
+        end: usize,
+        chunk_size: usize,
+    ) -> HashMap<usize, Chain> {
+        self.bin_walks(arena, start, end, chunk_size)
+            .into_iter()
+            .map(|(index, (_, chain))| (index, chain))
+            .collect()
+    }
 
-            let mem = match self.proc.read(head_addr, chunk_size) {
-                Some(m) => m,
-                None => continue,
-            };
-            let mut chunk = MallocChunk::new(&self.malloc_chunk, mem, head_addr);
+    /// Follows the `bk` links of glibc bins `start..end` back to their
+    /// heads, reading `chunk_size` bytes of every chunk.
+    fn bin_walks(
+        &self,
+        arena: &MallocState,
+        start: usize,
+        end: usize,
+        chunk_size: usize,
+    ) -> HashMap<usize, (Vec<MallocChunk>, Chain)> {
+        let indices: Vec<usize> = (start..end).collect();
+        let mut heads = Vec::with_capacity(indices.len());
+        let mut walks: Vec<ListWalk> = indices.iter().map(|_| ListWalk::default()).collect();
+        let mut starts = Vec::with_capacity(indices.len());
+        for (&index, walk) in indices.iter().zip(walks.iter_mut()) {
+            // bin_at(i) points two size_t before its fd slot.
+            let head = arena
+                .addrof(&format!("bins[{}]", (index - 1) * 2))
+                .map_or(0, |a| a.saturating_sub(2 * self.size_t as u64));
+            let last = arena.bin_bk(index).unwrap_or(head);
+            heads.push(head);
+            starts.push((head != 0 && last != head && walk.seen.insert(last)).then_some(last));
+        }
 
-            while chunk.bk() != head_addr {
-                let next = chunk.bk();
-                if !seen.insert(next) {
-                    cycle = true;
-                    break;
-                }
-                let mem = match self.proc.read(next, chunk_size) {
-                    Some(m) => m,
-                    None => break,
-                };
-                chunk = MallocChunk::new(&self.malloc_chunk, mem, next);
-                addrs.push(chunk.addr());
+        self.walk_lists(starts, chunk_size, |i, chunk| {
+            let walk = &mut walks[i];
+            let next = chunk.bk();
+            walk.links.push(self.make_link(next, next, 0));
+            walk.chunks.push(chunk);
+            if next == heads[i] {
+                return None;
             }
-
-            if !addrs.is_empty() {
-                result.insert(index, Chain { addrs, cycle });
+            if !walk.seen.insert(next) {
+                walk.cycle = true;
+                return None;
             }
-        }
-        result
+            Some(next)
+        });
+
+        indices
+            .into_iter()
+            .zip(walks)
+            .map(|(index, walk)| (index, walk.finish()))
+            .filter(|(_, (chunks, _))| !chunks.is_empty())
+            .collect()
     }
 
     pub fn unsortedbins(&self) -> Vec<MallocChunk> {
-        let bins = self.bins(0, 1, 0x20);
-        bins.get(&0).cloned().unwrap_or_default()
+        self.main_arena()
+            .map(|arena| self.arena_unsortedbins(&arena))
+            .unwrap_or_default()
     }
 
     pub fn unsortedbin_chain(&self) -> Option<Chain> {
-        let bins = self.bin_chains(0, 1, 0x20);
-        bins.get(&0).cloned()
+        self.arena_unsortedbin_chain(&self.main_arena()?)
     }
 
     pub fn smallbins(&self) -> HashMap<usize, Vec<MallocChunk>> {
-        self.bins(2, 64, 0x20)
+        self.bins(SMALLBINS.start, SMALLBINS.end, self.small_chunk_len())
     }
 
     pub fn smallbin_chains(&self) -> HashMap<usize, Chain> {
-        self.bin_chains(2, 64, 0x20)
+        self.bin_chains(SMALLBINS.start, SMALLBINS.end, self.small_chunk_len())
     }
 
     pub fn largebins(&self) -> HashMap<usize, Vec<MallocChunk>> {
-        self.bins(64, 127, 0x30)
+        self.bins(LARGEBINS.start, LARGEBINS.end, self.malloc_chunk.size())
     }
 
     pub fn largebin_chains(&self) -> HashMap<usize, Chain> {
-        self.bin_chains(64, 127, 0x30)
+        self.bin_chains(LARGEBINS.start, LARGEBINS.end, self.malloc_chunk.size())
     }
 
-    pub fn record(&self) -> HeapRecord {
-        HeapRecord::new(self)
+    pub fn arena_unsortedbins(&self, arena: &MallocState) -> Vec<MallocChunk> {
+        self.arena_bins(arena, UNSORTED_BIN, UNSORTED_BIN + 1, self.small_chunk_len())
+            .remove(&UNSORTED_BIN)
+            .unwrap_or_default()
     }
 
-    fn libc_base(&self) -> u64 {
-        if self.libc_base != 0 {
-            self.libc_base
-        } else {
-            first_base(&self.proc.bases(), "libc")
+    pub fn arena_unsortedbin_chain(&self, arena: &MallocState) -> Option<Chain> {
+        self.arena_bin_chains(arena, UNSORTED_BIN, UNSORTED_BIN + 1, self.small_chunk_len())
+            .remove(&UNSORTED_BIN)
+    }
+
+    pub fn arena_smallbins(&self, arena: &MallocState) -> HashMap<usize, Vec<MallocChunk>> {
+        self.arena_bins(arena, SMALLBINS.start, SMALLBINS.end, self.small_chunk_len())
+    }
+
+    pub fn arena_smallbin_chains(&self, arena: &MallocState) -> HashMap<usize, Chain> {
+        self.arena_bin_chains(arena, SMALLBINS.start, SMALLBINS.end, self.small_chunk_len())
+    }
+
+    pub fn arena_largebins(&self, arena: &MallocState) -> HashMap<usize, Vec<MallocChunk>> {
+        self.arena_bins(arena, LARGEBINS.start, LARGEBINS.end, self.malloc_chunk.size())
+    }
+
+    pub fn arena_largebin_chains(&self, arena: &MallocState) -> HashMap<usize, Chain> {
+        self.arena_bin_chains(arena, LARGEBINS.start, LARGEBINS.end, self.malloc_chunk.size())
+    }
+
+    /// `top` and `binmap` of `arena` checked against its heaps and bins.
+    pub fn arena_issues(&self, arena: &MallocState) -> Vec<ArenaIssue> {
+        let mut issues = Vec::new();
+        let top = arena.top();
+        let heaps = self.arena_heaps(arena);
+        match heaps.iter().find(|r| r.contains(&top)) {
+            None if top != 0 => issues.push(ArenaIssue::TopOutsideHeap { top }),
+            None => {}
+            Some(heap) => {
+                if let Some(chunk) = self.chunk_at(top) {
+                    let size = chunk.size();
+                    if let Some(system_mem) = arena.system_mem()
+                        && size > system_mem
+                    {
+                        issues.push(ArenaIssue::TopSize {
+                            top,
+                            size,
+                            system_mem,
+                        });
+                    }
+                    // The top chunk always runs to the end of its heap.
+                    if top.wrapping_add(size) != heap.end {
+                        issues.push(ArenaIssue::TopEnd {
+                            top,
+                            end: top.wrapping_add(size),
+                            heap_end: heap.end,
+                        });
+                    }
+                    if !chunk.prev_inuse() {
+                        issues.push(ArenaIssue::TopPrevFree { top });
+                    }
+                }
+            }
         }
+
+        let bins = self.arena_smallbins(arena).into_keys();
+        let mut filled: Vec<usize> = bins.chain(self.arena_largebins(arena).into_keys()).collect();
+        filled.sort_unstable();
+        for index in filled {
+            if arena.bin_marked(index) == Some(false) {
+                issues.push(ArenaIssue::Unmarked { index });
+            }
+        }
+        issues
     }
 
-    fn heap_base(&self) -> u64 {
-        if self.heap_base != 0 {
-            self.heap_base
-        } else {
-            first_base(&self.proc.bases(), "heap")
+    pub fn largebin_rings(&self) -> HashMap<usize, NextsizeRing> {
+        self.main_arena()
+            .map(|arena| self.arena_largebin_rings(&arena))
+            .unwrap_or_default()
+    }
+
+    /// The `fd_nextsize` ring of every non-empty large bin of `arena`.
+    pub fn arena_largebin_rings(&self, arena: &MallocState) -> HashMap<usize, NextsizeRing> {
+        let heaps = self.arena_heaps(arena);
+        self.arena_largebins(arena)
+            .into_iter()
+            .map(|(index, chunks)| (index, self.nextsize_ring(&chunks, &heaps)))
+            .collect()
+    }
+
+    /// Follows `fd_nextsize` from the first chunk of a large bin, whose
+    /// `bk` walk is `chunks`, and checks it against the bin's order.
+    fn nextsize_ring(&self, chunks: &[MallocChunk], heaps: &[Range<u64>]) -> NextsizeRing {
+        let mut ring = NextsizeRing::default();
+        // The bk walk runs smallest first; fd order is largest first.
+        let fd_order: Vec<&MallocChunk> = chunks.iter().rev().collect();
+        for pair in fd_order.windows(2) {
+            if pair[1].size() > pair[0].size() {
+                ring.issues.push(NextsizeIssue::Unordered {
+                    addr: pair[1].addr(),
+                    size: pair[1].size(),
+                    prev: pair[0].size(),
+                });
+            }
+        }
+        for chunk in &fd_order {
+            let bk = chunk.bk_nextsize();
+            if bk != 0 && !heaps.iter().any(|r| r.contains(&bk)) {
+                ring.issues.push(NextsizeIssue::OutsideHeap {
+                    addr: chunk.addr(),
+                    bk_nextsize: bk,
+                });
+            }
+        }
+
+        let Some(&first) = fd_order.first() else {
+            return ring;
+        };
+        let position: HashMap<u64, usize> =
+            fd_order.iter().enumerate().map(|(i, c)| (c.addr(), i)).collect();
+        let mut seen = HashSet::new();
+        let mut cur = first.clone();
+        loop {
+            seen.insert(cur.addr());
+            let group = match position.get(&cur.addr()) {
+                Some(&i) => fd_order[i..]
+                    .iter()
+                    .take_while(|c| c.size() == cur.size())
+                    .map(|&c| c.clone())
+                    .collect(),
+                None => {
+                    ring.issues.push(NextsizeIssue::NotInBin { addr: cur.addr() });
+                    vec![cur.clone()]
+                }
+            };
+            ring.groups.push(SizeGroup {
+                size: cur.size(),
+                chunks: group,
+            });
+
+            let fd = self.chunk_at(cur.fd_nextsize());
+            let bk = self.chunk_at(cur.bk_nextsize());
+            let links = [
+                ("fd_nextsize", cur.fd_nextsize(), fd.as_ref().map(|c| c.bk_nextsize())),
+                ("bk_nextsize", cur.bk_nextsize(), bk.as_ref().map(|c| c.fd_nextsize())),
+            ];
+            for (field, target, back) in links {
+                if back != Some(cur.addr()) {
+                    ring.issues.push(NextsizeIssue::BrokenLink {
+                        addr: cur.addr(),
+                        field,
+                        target,
+                        back,
+                    });
+                }
+            }
+
+            let next = cur.fd_nextsize();
+            if next == first.addr() {
+                ring.closed = true;
+                break;
+            }
+            match fd {
+                Some(chunk) if next != 0 && !seen.contains(&next) => cur = chunk,
+                _ => break,
+            }
         }
+
+        // Size groups the ring never reached.
+        let mut prev_size = None;
+        for chunk in &fd_order {
+            if prev_size != Some(chunk.size()) && !seen.contains(&chunk.addr()) {
+                ring.issues.push(NextsizeIssue::Skipped { addr: chunk.addr() });
+            }
+            prev_size = Some(chunk.size());
+        }
+        ring
+    }
+
+    /// Header plus fd/bk: what a small or unsorted bin walk reads per chunk.
+    fn small_chunk_len(&self) -> usize {
+        4 * self.size_t
     }
 }
 
@@ -586,10 +1814,13 @@ pub struct HeapRecord {
     pub arch: Arch,
     pub libc_version: String,
     pub tcache_enable: bool,
+    #[allow(dead_code)]
     pub libc_path: Option<String>,
+    #[allow(dead_code)]
     pub exe_path: PathBuf,
     pub size_t: usize,
     pub main_arena: Option<MallocState>,
+    pub malloc_par: Option<MallocPar>,
     pub tcache: Option<Tcache>,
     pub heap_chunks: Vec<MallocChunk>,
     pub fastbins: HashMap<usize, Vec<MallocChunk>>,
@@ -597,38 +1828,469 @@ pub struct HeapRecord {
     pub smallbins: HashMap<usize, Vec<MallocChunk>>,
     pub largebins: HashMap<usize, Vec<MallocChunk>>,
     pub tcache_chunks: HashMap<usize, Vec<MallocChunk>>,
+    /// Thread arenas; `main_arena` is described by the fields above.
+    pub arenas: Vec<ArenaRecord>,
+    pub threads: Vec<ThreadRecord>,
+    /// IS_MMAPPED chunks, outside every arena.
+    pub mmapped_chunks: Vec<MallocChunk>,
     pub libc_base: u64,
     pub heap_base: u64,
+    #[allow(dead_code)]
     pub bases: HashMap<String, Vec<u64>>,
+    #[allow(dead_code)]
     pub ranges: HashMap<String, Vec<Range<u64>>>,
 }
 
 impl HeapRecord {
-    pub fn new(hi: &HeapInspector) -> Self {
+    pub fn new(snap: &Snapshot) -> Self {
+        let info = snap.info();
+        let arenas = snap.arenas();
+        Self {
+            pid: info.pid,
+            arch: info.arch,
+            libc_version: info.libc_version.clone(),
+            tcache_enable: info.tcache_enable,
+            libc_path: info.libc_path.clone(),
+            exe_path: info.exe_path.clone(),
+            size_t: snap.size_t,
+            main_arena: arenas.first().cloned(),
+            malloc_par: snap.malloc_par(),
+            tcache: snap.tcache(),
+            heap_chunks: snap.heap_chunks(),
+            fastbins: snap.fastbins(),
+            unsortedbins: snap.unsortedbins(),
+            smallbins: snap.smallbins(),
+            largebins: snap.largebins(),
+            tcache_chunks: snap.tcache_chunks(),
+            arenas: arenas
+                .iter()
+                .skip(1)
+                .map(|arena| snap.arena_record(arena))
+                .collect(),
+            threads: snap.threads(),
+            mmapped_chunks: snap.mmapped_chunks(),
+            libc_base: snap.libc_base(),
+            heap_base: snap.heap_base(),
+            bases: snap.bases(),
+            ranges: snap.ranges(),
+        }
+    }
+
+    /// Every chunk on a free list, with the list it was found on.
+    pub fn bin_entries(&self) -> Vec<(BinSlot, &MallocChunk)> {
+        let mut lists = vec![
+            (BinOwner::Main, BinKind::Tcache, sorted_bins(&self.tcache_chunks)),
+            (BinOwner::Main, BinKind::Fastbin, sorted_bins(&self.fastbins)),
+            (BinOwner::Main, BinKind::Unsorted, vec![(0, &self.unsortedbins)]),
+            (BinOwner::Main, BinKind::Smallbin, sorted_bins(&self.smallbins)),
+            (BinOwner::Main, BinKind::Largebin, sorted_bins(&self.largebins)),
+        ];
+        // The main thread's tcache is already listed as the main one.
+        let main_tcache = self.tcache.as_ref().map(|t| t.addr());
+        for thread in &self.threads {
+            if thread.tcache.as_ref().map(|t| t.addr()) != main_tcache {
+                let owner = BinOwner::Thread(thread.tid);
+                lists.push((owner, BinKind::Tcache, sorted_bins(&thread.tcache_chunks)));
+            }
+        }
+        for arena in &self.arenas {
+            let owner = BinOwner::Arena(arena.addr);
+            lists.push((owner, BinKind::Fastbin, sorted_bins(&arena.fastbins)));
+            lists.push((owner, BinKind::Unsorted, vec![(0, &arena.unsortedbins)]));
+            lists.push((owner, BinKind::Smallbin, sorted_bins(&arena.smallbins)));
+            lists.push((owner, BinKind::Largebin, sorted_bins(&arena.largebins)));
+        }
+
+        let mut out = Vec::new();
+        for (owner, kind, bins) in lists {
+            for (index, chunks) in bins {
+                let slot = BinSlot { owner, kind, index };
+                out.extend(chunks.iter().map(|chunk| (slot, chunk)));
+            }
+        }
+        out
+    }
+
+    /// Free lists of every chunk address. A chunk listed more than once
+    /// has several slots.
+    pub fn bin_slots(&self) -> HashMap<u64, Vec<BinSlot>> {
+        let mut out: HashMap<u64, Vec<BinSlot>> = HashMap::new();
+        for (slot, chunk) in self.bin_entries() {
+            let slots = out.entry(chunk.addr()).or_default();
+            if !slots.contains(&slot) {
+                slots.push(slot);
+            }
+        }
+        out
+    }
+
+    /// `top` of every arena, `main_arena` first.
+    pub fn tops(&self) -> Vec<u64> {
+        self.main_arena
+            .iter()
+            .map(|a| a.top())
+            .chain(self.arenas.iter().map(|a| a.state.top()))
+            .collect()
+    }
+
+    /// Chunks of every arena's heaps and the mmapped ones, by address.
+    pub fn all_chunks(&self) -> Vec<&MallocChunk> {
+        let mut out: Vec<&MallocChunk> = self
+            .heap_chunks
+            .iter()
+            .chain(self.arenas.iter().flat_map(|a| a.heap_chunks.iter()))
+            .chain(self.mmapped_chunks.iter())
+            .collect();
+        out.sort_by_key(|c| c.addr());
+        out
+    }
+
+    /// Every walked chunk with its state, and the free-listed chunks the
+    /// heap walk never reached, by address.
+    pub fn chunk_classes(&self) -> Vec<ChunkClass> {
+        let tops = self.tops();
+        let mut slots = self.bin_slots();
+        let walks = std::iter::once(&self.heap_chunks)
+            .chain(self.arenas.iter().map(|a| &a.heap_chunks))
+            .chain(std::iter::once(&self.mmapped_chunks));
+        let mut out = Vec::new();
+        for chunks in walks {
+            for (i, chunk) in chunks.iter().enumerate() {
+                let addr = chunk.addr();
+                // The successor's PREV_INUSE is the only record of whether
+                // the chunk is free; mmapped chunks have no successor.
+                let next_prev_inuse = chunks
+                    .get(i + 1)
+                    .filter(|n| n.addr() == chunk.next_addr() && !chunk.is_mmapped())
+                    .map(|n| n.prev_inuse());
+                let bins = slots.remove(&addr).unwrap_or_default();
+                let top = tops.contains(&addr);
+                out.push(ChunkClass::new(addr, chunk.size(), top, bins, next_prev_inuse));
+            }
+        }
+
+        let sizes: HashMap<u64, u64> = self
+            .bin_entries()
+            .into_iter()
+            .map(|(_, c)| (c.addr(), c.size()))
+            .collect();
+        for (addr, bins) in slots {
+            let size = sizes.get(&addr).copied().unwrap_or(0);
+            let mut class = ChunkClass::new(addr, size, false, bins, None);
+            class.issues.push(ChunkIssue::OutsideHeap);
+            out.push(class);
+        }
+        out.sort_by_key(|c| c.addr);
+        out
+    }
+}
+
+/// What the heap walk and the free lists together say about a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    InUse,
+    Top,
+    /// On a free list; the first one when it is on several.
+    Free(BinSlot),
+}
+
+impl fmt::Display for ChunkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InUse => write!(f, "in-use"),
+            Self::Top => write!(f, "top"),
+            Self::Free(slot) => write!(f, "{}", slot),
+        }
+    }
+}
+
+/// Disagreement between the heap walk and the free lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkIssue {
+    /// In a bin whose chunks clear the successor's PREV_INUSE, but the
+    /// successor has it set.
+    NextInUse(BinSlot),
+    /// Not in such a bin, but the successor's PREV_INUSE is clear.
+    NextFree,
+    /// On more than one free list.
+    MultipleBins(Vec<BinSlot>),
+    /// `top` is also on a free list.
+    TopInBin(BinSlot),
+    /// On a free list, but not reached by any heap walk.
+    OutsideHeap,
+}
+
+impl fmt::Display for ChunkIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NextInUse(slot) => write!(f, "in {} but next chunk has PREV_INUSE set", slot),
+            Self::NextFree => write!(f, "not binned but next chunk has PREV_INUSE clear"),
+            Self::MultipleBins(slots) => {
+                let slots: Vec<String> = slots.iter().map(|s| s.to_string()).collect();
+                write!(f, "in several bins: {}", slots.join(", "))
+            }
+            Self::TopInBin(slot) => write!(f, "top is in {}", slot),
+            Self::OutsideHeap => write!(f, "not reached by the heap walk"),
+        }
+    }
+}
+
+/// One chunk, classified.
+#[derive(Debug, Clone)]
+pub struct ChunkClass {
+    pub addr: u64,
+    pub size: u64,
+    pub state: ChunkState,
+    pub issues: Vec<ChunkIssue>,
+}
+
+impl ChunkClass {
+    fn new(
+        addr: u64,
+        size: u64,
+        top: bool,
+        bins: Vec<BinSlot>,
+        next_prev_inuse: Option<bool>,
+    ) -> Self {
+        let mut issues = Vec::new();
+        let state = match bins.first() {
+            Some(&slot) if top => {
+                issues.push(ChunkIssue::TopInBin(slot));
+                ChunkState::Top
+            }
+            _ if top => ChunkState::Top,
+            Some(&slot) => ChunkState::Free(slot),
+            None => ChunkState::InUse,
+        };
+        if bins.len() > 1 {
+            issues.push(ChunkIssue::MultipleBins(bins.clone()));
+        }
+        // tcache and fastbin chunks stay in use as far as their neighbours
+        // are concerned.
+        let consolidated = bins.iter().find(|b| {
+            matches!(b.kind, BinKind::Unsorted | BinKind::Smallbin | BinKind::Largebin)
+        });
+        match (next_prev_inuse, consolidated) {
+            (Some(true), Some(&slot)) if !top => issues.push(ChunkIssue::NextInUse(slot)),
+            (Some(false), None) if !top => issues.push(ChunkIssue::NextFree),
+            _ => {}
+        }
         Self {
-            pid: hi.pid(),
-            arch: hi.arch(),
-            libc_version: hi.libc_version().to_string(),
-            tcache_enable: hi.tcache_enable,
-            libc_path: hi.libc_path().map(|s| s.to_string()),
-            exe_path: hi.exe_path.clone(),
-            size_t: hi.size_t,
-            main_arena: hi.main_arena(),
-            tcache: hi.tcache(),
-            heap_chunks: hi.heap_chunks(),
-            fastbins: hi.fastbins(),
-            unsortedbins: hi.unsortedbins(),
-            smallbins: hi.smallbins(),
-            largebins: hi.largebins(),
-            tcache_chunks: hi.tcache_chunks(),
-            libc_base: hi.libc_base(),
-            heap_base: hi.heap_base(),
-            bases: hi.bases(),
-            ranges: hi.ranges(),
+            addr,
+            size,
+            state,
+            issues,
+        }
+    }
+}
+
+/// Kind of free list a chunk sits in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinKind {
+    Tcache,
+    Fastbin,
+    Unsorted,
+    Smallbin,
+    Largebin,
+}
+
+/// Whose free list: `main_arena` (and the main thread's tcache), a thread
+/// arena by address, or the tcache of a thread by tid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOwner {
+    Main,
+    Arena(u64),
+    Thread(u64),
+}
+
+/// One free list entry, e.g. `smallbin[4]` of some arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BinSlot {
+    pub owner: BinOwner,
+    pub kind: BinKind,
+    pub index: usize,
+}
+
+impl fmt::Display for BinSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            BinKind::Tcache => "tcache",
+            BinKind::Fastbin => "fastbin",
+            BinKind::Unsorted => "unsortedbin",
+            BinKind::Smallbin => "smallbin",
+            BinKind::Largebin => "largebin",
+        };
+        match self.owner {
+            BinOwner::Main => {}
+            BinOwner::Arena(addr) => write!(f, "arena[0x{:x}].", addr)?,
+            BinOwner::Thread(tid) => write!(f, "thread[{}].", tid)?,
+        }
+        if self.kind == BinKind::Unsorted {
+            write!(f, "{}", kind)
+        } else {
+            write!(f, "{}[{}]", kind, self.index)
         }
     }
 }
 
+/// Problem with an arena's `top` or `binmap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArenaIssue {
+    /// `top` is outside every heap of the arena.
+    TopOutsideHeap { top: u64 },
+    /// Top size above `system_mem`, as after House of Force.
+    TopSize { top: u64, size: u64, system_mem: u64 },
+    /// Top does not end where its heap does, as after House of Orange.
+    TopEnd { top: u64, end: u64, heap_end: u64 },
+    /// PREV_INUSE of top is clear; a free chunk should have merged into it.
+    TopPrevFree { top: u64 },
+    /// Non-empty bin whose `binmap` bit is clear.
+    Unmarked { index: usize },
+}
+
+impl fmt::Display for ArenaIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TopOutsideHeap { top } => write!(f, "top 0x{:x} is outside the heap", top),
+            Self::TopSize {
+                top,
+                size,
+                system_mem,
+            } => write!(
+                f,
+                "top 0x{:x} size 0x{:x} exceeds system_mem 0x{:x}",
+                top, size, system_mem
+            ),
+            Self::TopEnd { top, end, heap_end } => write!(
+                f,
+                "top 0x{:x} ends at 0x{:x}, the heap at 0x{:x}",
+                top, end, heap_end
+            ),
+            Self::TopPrevFree { top } => write!(f, "top 0x{:x} has PREV_INUSE clear", top),
+            Self::Unmarked { index } => write!(f, "bin {} is not empty but unmarked in binmap", index),
+        }
+    }
+}
+
+/// Equal-sized chunks of a large bin in fd order; the first one carries
+/// the nextsize links.
+#[derive(Debug, Clone)]
+pub struct SizeGroup {
+    pub size: u64,
+    pub chunks: Vec<MallocChunk>,
+}
+
+/// The `fd_nextsize` ring of one large bin.
+#[derive(Debug, Clone, Default)]
+pub struct NextsizeRing {
+    /// Size groups in ring order, starting at the bin's first chunk.
+    pub groups: Vec<SizeGroup>,
+    /// The ring led back to the first chunk.
+    pub closed: bool,
+    pub issues: Vec<NextsizeIssue>,
+}
+
+/// Disagreement between a large bin and its nextsize links.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NextsizeIssue {
+    /// Larger than the chunk before it in fd order.
+    Unordered { addr: u64, size: u64, prev: u64 },
+    /// `p->fd_nextsize->bk_nextsize != p` (or the `bk_nextsize` mirror);
+    /// `back` is what the target holds, if readable.
+    BrokenLink {
+        addr: u64,
+        field: &'static str,
+        target: u64,
+        back: Option<u64>,
+    },
+    /// `bk_nextsize` leaves the arena's heaps, the largebin attack write.
+    OutsideHeap { addr: u64, bk_nextsize: u64 },
+    /// On the ring but not in the bin.
+    NotInBin { addr: u64 },
+    /// First chunk of a size group the ring does not reach.
+    Skipped { addr: u64 },
+}
+
+impl NextsizeIssue {
+    pub fn addr(&self) -> u64 {
+        match *self {
+            Self::Unordered { addr, .. }
+            | Self::BrokenLink { addr, .. }
+            | Self::OutsideHeap { addr, .. }
+            | Self::NotInBin { addr }
+            | Self::Skipped { addr } => addr,
+        }
+    }
+}
+
+impl fmt::Display for NextsizeIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unordered { addr, size, prev } => write!(
+                f,
+                "0x{:x} size 0x{:x} follows a smaller chunk (0x{:x})",
+                addr, size, prev
+            ),
+            Self::BrokenLink {
+                addr,
+                field,
+                target,
+                back,
+            } => {
+                let mirror = if *field == "fd_nextsize" {
+                    "bk_nextsize"
+                } else {
+                    "fd_nextsize"
+                };
+                let back = back.map_or("unmapped".to_string(), |b| format!("0x{:x}", b));
+                write!(
+                    f,
+                    "0x{:x} {} 0x{:x} has {} {}",
+                    addr, field, target, mirror, back
+                )
+            }
+            Self::OutsideHeap { addr, bk_nextsize } => write!(
+                f,
+                "0x{:x} bk_nextsize 0x{:x} is outside the heap",
+                addr, bk_nextsize
+            ),
+            Self::NotInBin { addr } => write!(f, "0x{:x} is on the ring but not in the bin", addr),
+            Self::Skipped { addr } => write!(f, "0x{:x} is not on the ring", addr),
+        }
+    }
+}
+
+/// One thread arena and the heaps it allocates from.
+#[derive(Debug, Clone)]
+pub struct ArenaRecord {
+    pub addr: u64,
+    pub state: MallocState,
+    pub heaps: Vec<Range<u64>>,
+    pub heap_chunks: Vec<MallocChunk>,
+    pub fastbins: HashMap<usize, Vec<MallocChunk>>,
+    pub unsortedbins: Vec<MallocChunk>,
+    pub smallbins: HashMap<usize, Vec<MallocChunk>>,
+    pub largebins: HashMap<usize, Vec<MallocChunk>>,
+}
+
+/// One thread of the target and the allocator state in its TLS.
+#[derive(Debug, Clone)]
+pub struct ThreadRecord {
+    pub tid: u64,
+    /// Thread pointer; the `%gs` base on i386.
+    pub fs_base: u64,
+    /// `thread_arena`, 0 until the thread first allocates.
+    pub arena: u64,
+    pub tcache: Option<Tcache>,
+    pub tcache_chunks: HashMap<usize, Vec<MallocChunk>>,
+}
+
+fn sorted_bins(bins: &HashMap<usize, Vec<MallocChunk>>) -> Vec<(usize, &Vec<MallocChunk>)> {
+    let mut out: Vec<(usize, &Vec<MallocChunk>)> = bins.iter().map(|(&i, c)| (i, c)).collect();
+    out.sort_by_key(|(i, _)| *i);
+    out
+}
+
 fn first_base(bases: &HashMap<String, Vec<u64>>, key: &str) -> u64 {
     bases
         .get(key)
@@ -636,17 +2298,62 @@ fn first_base(bases: &HashMap<String, Vec<u64>>, key: &str) -> u64 {
         .unwrap_or(0)
 }
 
-fn default_lib_paths() -> (Option<String>, Option<String>) {
-    let libc_candidates = [
-        "/usr/lib/libc.so.6",
-        "/lib/x86_64-linux-gnu/libc.so.6",
-        "/lib64/libc.so.6",
-    ];
-    let ld_candidates = [
-        "/usr/lib64/ld-linux-x86-64.so.2",
-        "/lib64/ld-linux-x86-64.so.2",
-        "/lib/x86_64-linux-gnu/ld-linux-x86-64.so.2",
-    ];
+/// The libc a core file names, if it is the build the core was dumped
+/// with: its build-id must match the one in the core's copy of libc's
+/// header page.
+fn core_libc(
+    proc: &Proc,
+    libc_base: u64,
+    path: Option<String>,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let Some(path) = path else {
+        warnings.push("the core maps no libc; pass --libc <path>".to_string());
+        return None;
+    };
+    let dumped = proc
+        .read(libc_base, PAGE_SIZE as usize)
+        .and_then(|page| Elf::from_image(page).ok()?.build_id());
+    let on_disk = Elf::open(&path).ok().and_then(|elf| elf.build_id());
+    if dumped.is_some() && dumped == on_disk {
+        return Some(path);
+    }
+    warnings.push(format!(
+        "{} does not match the libc in the core; pass --libc <path>",
+        path
+    ));
+    None
+}
+
+/// The host's libc and ld.so for `arch`, from the usual multiarch, lib32
+/// and lib64 locations.
+pub fn default_lib_paths(arch: Arch) -> (Option<String>, Option<String>) {
+    let (libc_candidates, ld_candidates): (&[&str], &[&str]) = match arch {
+        Arch::X64 => (
+            &[
+                "/usr/lib/libc.so.6",
+                "/lib/x86_64-linux-gnu/libc.so.6",
+                "/lib64/libc.so.6",
+            ],
+            &[
+                "/usr/lib64/ld-linux-x86-64.so.2",
+                "/lib64/ld-linux-x86-64.so.2",
+                "/lib/x86_64-linux-gnu/ld-linux-x86-64.so.2",
+            ],
+        ),
+        Arch::X86 => (
+            &[
+                "/usr/lib32/libc.so.6",
+                "/lib/i386-linux-gnu/libc.so.6",
+                "/lib32/libc.so.6",
+            ],
+            &[
+                "/lib/ld-linux.so.2",
+                "/usr/lib32/ld-linux.so.2",
+                "/lib/i386-linux-gnu/ld-linux.so.2",
+            ],
+        ),
+    };
 
     let libc_path = libc_candidates
         .iter()
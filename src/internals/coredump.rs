@@ -0,0 +1,289 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use super::common::{uk64, Arch};
+use super::elf::{Elf, Segment};
+
+const ET_CORE: u64 = 4;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_386_TLS: u32 = 0x200;
+const NT_FILE: u32 = 0x46494c45;
+
+/// How far past the executable the kernel may randomize the brk start:
+/// 32MB for 32-bit tasks, up to 1GB for 64-bit ones.
+fn brk_random_range(arch: Arch) -> u64 {
+    match arch {
+        Arch::X86 => 0x2000000 + 0x1000,
+        Arch::X64 => 0x40000000 + 0x1000,
+    }
+}
+
+/// One file mapping listed in NT_FILE.
+#[derive(Debug, Clone)]
+struct FileMapping {
+    range: Range<u64>,
+    offset: u64,
+    path: String,
+}
+
+/// A thread as recorded by its NT_PRSTATUS note.
+#[derive(Debug, Clone)]
+pub struct CoreThread {
+    pub tid: u64,
+    pub stack_pointer: u64,
+    /// `fs_base` on x86_64, the `%gs` segment base on i386.
+    pub thread_pointer: u64,
+}
+
+/// Memory, mappings and threads of a process, taken from an ELF core file.
+/// Pages the kernel did not dump (unmodified file mappings) are read from
+/// the mapped files when they are available.
+#[derive(Debug)]
+pub struct CoreDump {
+    elf: Elf,
+    pid: u64,
+    loads: Vec<Segment>,
+    files: Vec<FileMapping>,
+    threads: Vec<CoreThread>,
+    substitutes: Vec<(String, String)>,
+}
+
+impl CoreDump {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let elf = Elf::open(path)?;
+        if elf.data().get(0x10..0x12).map(uk64) != Some(ET_CORE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a core file", path),
+            ));
+        }
+        let loads = elf
+            .segments()
+            .into_iter()
+            .filter(|s| s.typ == PT_LOAD)
+            .collect();
+        let mut core = Self {
+            elf,
+            pid: 0,
+            loads,
+            files: Vec::new(),
+            threads: Vec::new(),
+            substitutes: Vec::new(),
+        };
+        core.read_notes();
+        Ok(core)
+    }
+
+    /// Reads pages of `original` from `replacement` instead, e.g. the copy
+    /// of the target's libc on the analysis machine.
+    pub fn substitute(&mut self, original: &str, replacement: &str) {
+        self.substitutes
+            .push((original.to_string(), replacement.to_string()));
+    }
+
+    pub fn arch(&self) -> Arch {
+        self.elf.arch()
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    /// Threads sorted by tid, as `/proc/<pid>/task` lists them.
+    pub fn threads(&self) -> &[CoreThread] {
+        &self.threads
+    }
+
+    /// The executable: the file mapped lowest in the address space.
+    pub fn exe_path(&self) -> Option<String> {
+        self.files
+            .iter()
+            .min_by_key(|f| f.range.start)
+            .map(|f| f.path.clone())
+    }
+
+    /// `(range, perm, name)` for every dumped mapping, named the way
+    /// `/proc/<pid>/maps` would name it.
+    pub fn maps(&self) -> Vec<(Range<u64>, String, String)> {
+        let heap = self.heap_start();
+        let sp = self
+            .threads
+            .iter()
+            .find(|t| t.tid == self.pid)
+            .map(|t| t.stack_pointer);
+        self.loads
+            .iter()
+            .map(|seg| {
+                let range = seg.vaddr..seg.vaddr + seg.memsz;
+                let perm: String = [(PF_R, 'r'), (PF_W, 'w'), (PF_X, 'x')]
+                    .iter()
+                    .map(|&(bit, c)| if seg.flags & bit != 0 { c } else { '-' })
+                    .chain(std::iter::once('p'))
+                    .collect();
+                let name = match self.file_at(seg.vaddr) {
+                    Some(f) => f.path.clone(),
+                    None if heap == Some(seg.vaddr) => "[heap]".to_string(),
+                    None if sp.is_some_and(|sp| range.contains(&sp)) => "[stack]".to_string(),
+                    None => String::new(),
+                };
+                (range, perm, name)
+            })
+            .collect()
+    }
+
+    pub fn read(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        let end = addr.checked_add(size as u64)?;
+        let mut out = Vec::with_capacity(size);
+        let mut cur = addr;
+        while cur < end {
+            let seg = self
+                .loads
+                .iter()
+                .find(|s| (s.vaddr..s.vaddr + s.memsz).contains(&cur))?;
+            let off = cur - seg.vaddr;
+            let dumped = off < seg.filesz;
+            let piece_end = if dumped {
+                (seg.vaddr + seg.filesz).min(end)
+            } else {
+                (seg.vaddr + seg.memsz).min(end)
+            };
+            let len = usize::try_from(piece_end - cur).ok()?;
+            if dumped {
+                let start = usize::try_from(seg.offset + off).ok()?;
+                out.extend_from_slice(self.elf.data().get(start..start + len)?);
+            } else {
+                out.extend(self.read_file(cur, len)?);
+            }
+            cur = piece_end;
+        }
+        Some(out)
+    }
+
+    fn file_at(&self, addr: u64) -> Option<&FileMapping> {
+        self.files.iter().find(|f| f.range.contains(&addr))
+    }
+
+    fn read_file(&self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let mapping = self.file_at(addr)?;
+        let path = self
+            .substitutes
+            .iter()
+            .find(|(orig, _)| *orig == mapping.path)
+            .map_or(mapping.path.as_str(), |(_, repl)| repl.as_str());
+        let mut f = File::open(path).ok()?;
+        f.seek(SeekFrom::Start(mapping.offset + addr - mapping.range.start))
+            .ok()?;
+        let mut buf = vec![0u8; len];
+        f.read_exact(&mut buf).ok()?;
+        Some(buf)
+    }
+
+    /// Start of the brk heap: the last anonymous writable mapping within
+    /// brk randomization range of the executable's end. A lone mapping
+    /// right at the end is the executable's `.bss` unless it is the only one.
+    fn heap_start(&self) -> Option<u64> {
+        let exe = self.exe_path()?;
+        let exe_end = self
+            .files
+            .iter()
+            .filter(|f| f.path == exe)
+            .map(|f| f.range.end)
+            .max()?;
+        let candidates: Vec<u64> = self
+            .loads
+            .iter()
+            .filter(|s| s.flags & PF_W != 0 && self.file_at(s.vaddr).is_none())
+            .map(|s| s.vaddr)
+            .filter(|&start| start >= exe_end && start < exe_end + brk_random_range(self.arch()))
+            .collect();
+        match candidates.as_slice() {
+            [] => None,
+            [only] if *only == exe_end => None,
+            _ => candidates.iter().copied().max(),
+        }
+    }
+
+    fn read_notes(&mut self) {
+        let word = if self.arch() == Arch::X64 { 8 } else { 4 };
+        // elf_prstatus: offsets of pr_pid and pr_reg, and the indices of
+        // the stack pointer and thread pointer (or %gs) in pr_reg.
+        let (pid_off, reg_off, sp_idx, tp_idx) = match self.arch() {
+            Arch::X64 => (32, 112, 19, 21),
+            Arch::X86 => (24, 72, 15, 10),
+        };
+        // elf_prpsinfo.pr_pid, the thread group id.
+        let psinfo_pid_off = if word == 8 { 24 } else { 12 };
+        let at = |desc: &[u8], off: usize| desc.get(off..off + word).map(uk64);
+
+        for note in self.elf.notes() {
+            match note.typ {
+                NT_PRSTATUS => {
+                    let reg = |idx: usize| at(&note.desc, reg_off + idx * word).unwrap_or(0);
+                    self.threads.push(CoreThread {
+                        tid: note.desc.get(pid_off..pid_off + 4).map_or(0, uk64),
+                        stack_pointer: reg(sp_idx),
+                        thread_pointer: reg(tp_idx),
+                    });
+                }
+                // user_desc entries of the thread above; %gs selects one.
+                NT_386_TLS => {
+                    if let Some(thread) = self.threads.last_mut() {
+                        let index = thread.thread_pointer >> 3;
+                        thread.thread_pointer = note
+                            .desc
+                            .chunks_exact(16)
+                            .find(|d| uk64(&d[..4]) == index)
+                            .map_or(0, |d| uk64(&d[4..8]));
+                    }
+                }
+                NT_PRPSINFO => {
+                    self.pid = note
+                        .desc
+                        .get(psinfo_pid_off..psinfo_pid_off + 4)
+                        .map_or(0, uk64);
+                }
+                NT_FILE => self.files = parse_nt_file(&note.desc, word),
+                _ => {}
+            }
+        }
+        // The thread that took the signal comes first in the core.
+        if self.pid == 0 {
+            self.pid = self.threads.first().map_or(0, |t| t.tid);
+        }
+        self.threads.sort_by_key(|t| t.tid);
+    }
+}
+
+/// NT_FILE: count and page size, `count` (start, end, page offset)
+/// triples, then the NUL separated file names.
+fn parse_nt_file(desc: &[u8], word: usize) -> Vec<FileMapping> {
+    let at = |i: usize| desc.get(i * word..(i + 1) * word).map(uk64);
+    let (count, page_size) = match (at(0), at(1)) {
+        (Some(c), Some(p)) => (c as usize, p),
+        _ => return Vec::new(),
+    };
+    let names_off = (2 + 3 * count) * word;
+    let names = desc
+        .get(names_off..)
+        .unwrap_or_default()
+        .split(|&b| b == 0)
+        .map(|n| String::from_utf8_lossy(n).to_string());
+
+    (0..count)
+        .zip(names)
+        .filter_map(|(i, path)| {
+            let base = 2 + 3 * i;
+            Some(FileMapping {
+                range: at(base)?..at(base + 1)?,
+                offset: at(base + 2)? * page_size,
+                path,
+            })
+        })
+        .collect()
+}
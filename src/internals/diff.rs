@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+
+use super::core::{BinSlot, HeapRecord};
+
+/// A chunk as one record saw it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkView {
+    pub addr: u64,
+    pub size: u64,
+    /// The chunk is some arena's `top`.
+    pub top: bool,
+    /// Free lists holding the chunk; empty when it is in use.
+    pub bins: Vec<BinSlot>,
+}
+
+/// How the chunks at some addresses changed between two records.
+#[derive(Debug, Clone)]
+pub enum ChunkChange {
+    /// New memory, or a chunk carved off `top`.
+    Created(ChunkView),
+    /// Memory that is no longer part of any heap (e.g. an unmapped chunk).
+    Removed(ChunkView),
+    /// One chunk became several.
+    Split {
+        before: ChunkView,
+        after: Vec<ChunkView>,
+    },
+    /// Several chunks became one.
+    Merged {
+        before: Vec<ChunkView>,
+        after: ChunkView,
+    },
+    /// Any other change of chunk boundaries.
+    Reshaped {
+        before: Vec<ChunkView>,
+        after: Vec<ChunkView>,
+    },
+    /// Same chunk, different free lists: freed, allocated or moved.
+    Rebinned { before: ChunkView, after: ChunkView },
+}
+
+impl ChunkChange {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Created(_) => "created",
+            Self::Removed(_) => "removed",
+            Self::Split { .. } => "split",
+            Self::Merged { .. } => "merged",
+            Self::Reshaped { before, after }
+                if before.len() == 1 && after.len() == 1 && before[0].addr == after[0].addr =>
+            {
+                "resized"
+            }
+            Self::Reshaped { .. } => "reshaped",
+            Self::Rebinned { before, after } => {
+                match (before.bins.is_empty(), after.bins.is_empty()) {
+                    (true, false) => "freed",
+                    (false, true) => "allocated",
+                    _ => "moved",
+                }
+            }
+        }
+    }
+
+    /// Lowest address involved, for ordering.
+    pub fn addr(&self) -> u64 {
+        match self {
+            Self::Created(c) | Self::Removed(c) => c.addr,
+            Self::Split { before, .. } => before.addr,
+            Self::Merged { after, .. } => after.addr,
+            Self::Reshaped { before, after } => before
+                .iter()
+                .chain(after)
+                .map(|c| c.addr)
+                .min()
+                .unwrap_or(0),
+            Self::Rebinned { before, .. } => before.addr,
+        }
+    }
+}
+
+/// A changed `malloc_state` field of the arena at `arena`.
+#[derive(Debug, Clone)]
+pub struct FieldChange {
+    pub arena: u64,
+    pub field: &'static str,
+    pub before: u64,
+    pub after: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HeapDiff {
+    pub chunks: Vec<ChunkChange>,
+    pub fields: Vec<FieldChange>,
+}
+
+impl HeapDiff {
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.fields.is_empty()
+    }
+}
+
+/// What changed from `before` to `after`.
+pub fn diff(before: &HeapRecord, after: &HeapRecord) -> HeapDiff {
+    let old = chunk_views(before);
+    let new = chunk_views(after);
+    HeapDiff {
+        chunks: chunk_changes(&old, &new),
+        fields: field_changes(before, after, &old, &new),
+    }
+}
+
+/// Chunks are matched by address and size; chunks whose boundaries moved
+/// are grouped by overlap.
+fn chunk_changes(old: &[ChunkView], new: &[ChunkView]) -> Vec<ChunkChange> {
+    let mut chunks = Vec::new();
+
+    // Unchanged boundaries: only the free lists can differ.
+    let new_by_addr: HashMap<u64, &ChunkView> = new.iter().map(|c| (c.addr, c)).collect();
+    let mut kept = HashSet::new();
+    for o in old {
+        if let Some(n) = new_by_addr.get(&o.addr)
+            && n.size == o.size
+        {
+            kept.insert(o.addr);
+            if n.bins != o.bins {
+                chunks.push(ChunkChange::Rebinned {
+                    before: o.clone(),
+                    after: (*n).clone(),
+                });
+            }
+        }
+    }
+
+    // Everything else, grouped into runs of overlapping chunks.
+    let mut items: Vec<(bool, &ChunkView)> = old
+        .iter()
+        .filter(|c| !kept.contains(&c.addr))
+        .map(|c| (false, c))
+        .chain(new.iter().filter(|c| !kept.contains(&c.addr)).map(|c| (true, c)))
+        .collect();
+    items.sort_by_key(|(is_new, c)| (c.addr, *is_new));
+    let mut group: (Vec<ChunkView>, Vec<ChunkView>) = (Vec::new(), Vec::new());
+    let mut group_end = 0;
+    for (is_new, chunk) in items {
+        // Free-list entries outside every heap have no size to overlap by.
+        let end = chunk.addr + chunk.size.max(1);
+        if chunk.addr >= group_end && !(group.0.is_empty() && group.1.is_empty()) {
+            chunks.extend(classify(std::mem::take(&mut group)));
+        }
+        group_end = if group.0.is_empty() && group.1.is_empty() {
+            end
+        } else {
+            group_end.max(end)
+        };
+        if is_new {
+            group.1.push(chunk.clone());
+        } else {
+            group.0.push(chunk.clone());
+        }
+    }
+    if !(group.0.is_empty() && group.1.is_empty()) {
+        chunks.extend(classify(group));
+    }
+    chunks.sort_by_key(ChunkChange::addr);
+    chunks
+}
+
+fn classify((before, after): (Vec<ChunkView>, Vec<ChunkView>)) -> Vec<ChunkChange> {
+    match (before.len(), after.len()) {
+        (0, _) => after.into_iter().map(ChunkChange::Created).collect(),
+        (_, 0) => before.into_iter().map(ChunkChange::Removed).collect(),
+        (1, 1) => vec![ChunkChange::Reshaped { before, after }],
+        // malloc served from top: everything but the new top is new.
+        (1, _) if before[0].top && after.last().is_some_and(|c| c.top) => after
+            .into_iter()
+            .filter(|c| !c.top)
+            .map(ChunkChange::Created)
+            .collect(),
+        (1, _) => vec![ChunkChange::Split {
+            before: before.into_iter().next().unwrap(),
+            after,
+        }],
+        (_, 1) => vec![ChunkChange::Merged {
+            before,
+            after: after.into_iter().next().unwrap(),
+        }],
+        _ => vec![ChunkChange::Reshaped { before, after }],
+    }
+}
+
+/// Heap, mmapped and free-listed chunks of `record`, by address.
+fn chunk_views(record: &HeapRecord) -> Vec<ChunkView> {
+    let tops = record.tops();
+    let mut slots = record.bin_slots();
+    let mut views: Vec<ChunkView> = record
+        .all_chunks()
+        .into_iter()
+        .map(|c| ChunkView {
+            addr: c.addr(),
+            size: c.size(),
+            top: tops.contains(&c.addr()),
+            bins: slots.remove(&c.addr()).unwrap_or_default(),
+        })
+        .collect();
+    // Listed chunks the heap walk did not reach, e.g. fake chunks.
+    views.extend(slots.into_iter().map(|(addr, bins)| ChunkView {
+        addr,
+        size: 0,
+        top: false,
+        bins,
+    }));
+    views.sort_by_key(|c| c.addr);
+    views
+}
+
+fn field_changes(
+    before: &HeapRecord,
+    after: &HeapRecord,
+    old: &[ChunkView],
+    new: &[ChunkView],
+) -> Vec<FieldChange> {
+    let size_at = |views: &[ChunkView], addr: u64| {
+        views.iter().find(|c| c.addr == addr).map_or(0, |c| c.size)
+    };
+    let arenas = |record: &HeapRecord| {
+        record
+            .main_arena
+            .iter()
+            .cloned()
+            .chain(record.arenas.iter().map(|a| a.state.clone()))
+            .collect::<Vec<_>>()
+    };
+    let old_arenas = arenas(before);
+    let mut out = Vec::new();
+    for n in arenas(after) {
+        let Some(o) = old_arenas.iter().find(|o| o.addr() == n.addr()) else {
+            continue;
+        };
+        let fields = [
+            ("top", o.top(), n.top()),
+            ("top_size", size_at(old, o.top()), size_at(new, n.top())),
+            ("last_remainder", o.last_remainder(), n.last_remainder()),
+            (
+                "system_mem",
+                o.system_mem().unwrap_or(0),
+                n.system_mem().unwrap_or(0),
+            ),
+        ];
+        for (field, before, after) in fields {
+            if before != after {
+                out.push(FieldChange {
+                    arena: n.addr(),
+                    field,
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internals::core::{BinKind, BinOwner};
+
+    fn chunk(addr: u64, size: u64) -> ChunkView {
+        ChunkView {
+            addr,
+            size,
+            top: false,
+            bins: Vec::new(),
+        }
+    }
+
+    fn top(addr: u64, size: u64) -> ChunkView {
+        ChunkView {
+            top: true,
+            ..chunk(addr, size)
+        }
+    }
+
+    fn binned(addr: u64, size: u64, kind: BinKind, index: usize) -> ChunkView {
+        ChunkView {
+            bins: vec![BinSlot {
+                owner: BinOwner::Main,
+                kind,
+                index,
+            }],
+            ..chunk(addr, size)
+        }
+    }
+
+    #[test]
+    fn malloc_from_top_is_created() {
+        let old = [chunk(0x1000, 0x20), top(0x1020, 0x1000)];
+        let new = [chunk(0x1000, 0x20), chunk(0x1020, 0x30), top(0x1050, 0xfd0)];
+        let changes = chunk_changes(&old, &new);
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            ChunkChange::Created(c) => assert_eq!(*c, chunk(0x1020, 0x30)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(changes[0].label(), "created");
+    }
+
+    #[test]
+    fn free_into_top_is_merged() {
+        let old = [chunk(0x1000, 0x20), chunk(0x1020, 0x30), top(0x1050, 0xfd0)];
+        let new = [chunk(0x1000, 0x20), top(0x1020, 0x1000)];
+        let changes = chunk_changes(&old, &new);
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            ChunkChange::Merged { before, after } => {
+                assert_eq!(*before, vec![chunk(0x1020, 0x30), top(0x1050, 0xfd0)]);
+                assert_eq!(*after, top(0x1020, 0x1000));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(changes[0].label(), "merged");
+    }
+
+    #[test]
+    fn bin_move_is_rebinned() {
+        let old = [binned(0x2000, 0x90, BinKind::Unsorted, 1), top(0x2090, 0xf70)];
+        let new = [binned(0x2000, 0x90, BinKind::Smallbin, 9), top(0x2090, 0xf70)];
+        let changes = chunk_changes(&old, &new);
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            ChunkChange::Rebinned { before, after } => {
+                assert_eq!(*before, old[0]);
+                assert_eq!(*after, new[0]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(changes[0].label(), "moved");
+    }
+
+    #[test]
+    fn free_and_allocate_are_rebinned() {
+        let inuse = [chunk(0x3000, 0x20)];
+        let freed = [binned(0x3000, 0x20, BinKind::Tcache, 0)];
+        assert_eq!(chunk_changes(&inuse, &freed)[0].label(), "freed");
+        assert_eq!(chunk_changes(&freed, &inuse)[0].label(), "allocated");
+    }
+}
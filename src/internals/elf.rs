@@ -0,0 +1,369 @@
+use std::fs;
+use std::io;
+
+use super::common::{uk64, Arch};
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+const SHT_DYNSYM: u32 = 11;
+const NT_GNU_BUILD_ID: u64 = 3;
+const PT_NOTE: u32 = 4;
+const PT_TLS: u32 = 7;
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub typ: u32,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub entsize: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub typ: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub name: String,
+    pub typ: u32,
+    pub desc: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+}
+
+/// Minimal ELF reader: just enough of the section table to look up
+/// symbols in libc and its debug files.
+#[derive(Debug, Clone)]
+pub struct Elf {
+    data: Vec<u8>,
+    arch: Arch,
+    sections: Vec<Section>,
+}
+
+impl Elf {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let data = fs::read(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to read ELF {}: {}", path, e))
+        })?;
+        Self::parse(data)
+    }
+
+    pub fn parse(data: Vec<u8>) -> io::Result<Self> {
+        let mut elf = Self::from_image(data)?;
+        elf.sections = elf.read_sections().ok_or_else(|| invalid("Truncated section table"))?;
+        Ok(elf)
+    }
+
+    /// The start of a mapped ELF image, e.g. a header page kept in a core
+    /// file. Only the ELF and program headers are read.
+    pub fn from_image(data: Vec<u8>) -> io::Result<Self> {
+        if data.len() < 0x34 || &data[..4] != b"\x7fELF" {
+            return Err(invalid("Not an ELF file"));
+        }
+        let arch = match data[4] {
+            1 => Arch::X86,
+            2 => Arch::X64,
+            other => return Err(invalid(&format!("Bad EI_CLASS field value: {:#x}", other))),
+        };
+        Ok(Self {
+            data,
+            arch,
+            sections: Vec::new(),
+        })
+    }
+
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.addr(0x18).unwrap_or(0)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn section_data(&self, section: &Section) -> Option<&[u8]> {
+        let start = usize::try_from(section.offset).ok()?;
+        let end = start.checked_add(usize::try_from(section.size).ok()?)?;
+        self.data.get(start..end)
+    }
+
+    /// Program headers.
+    pub fn segments(&self) -> Vec<Segment> {
+        self.read_segments().unwrap_or_default()
+    }
+
+    /// Size of the module's TLS block, from `PT_TLS`.
+    pub fn tls_size(&self) -> Option<u64> {
+        self.segments()
+            .into_iter()
+            .find(|s| s.typ == PT_TLS)
+            .map(|s| s.memsz)
+    }
+
+    /// Notes of every `PT_NOTE` segment, as found in core files.
+    pub fn notes(&self) -> Vec<Note> {
+        let mut out = Vec::new();
+        for segment in self.segments().iter().filter(|s| s.typ == PT_NOTE) {
+            let data = match self.segment_data(segment) {
+                Some(d) => d,
+                None => continue,
+            };
+            let mut off = 0;
+            while let Some((note, next)) = Self::note_at(data, off) {
+                out.push(note);
+                off = next;
+            }
+        }
+        out
+    }
+
+    fn segment_data(&self, segment: &Segment) -> Option<&[u8]> {
+        let start = usize::try_from(segment.offset).ok()?;
+        let end = start.checked_add(usize::try_from(segment.filesz).ok()?)?;
+        self.data.get(start..end)
+    }
+
+    /// The note at `off` in `data` and the offset of the one after it.
+    fn note_at(data: &[u8], off: usize) -> Option<(Note, usize)> {
+        let align4 = |n: usize| n.checked_add(3).map(|n| n & !3);
+        let header = data.get(off..off.checked_add(12)?)?;
+        let namesz = uk64(&header[0..4]) as usize;
+        let descsz = uk64(&header[4..8]) as usize;
+        let typ = uk64(&header[8..12]) as u32;
+        let name_start = off + 12;
+        let desc_start = name_start.checked_add(align4(namesz)?)?;
+        let name = data.get(name_start..name_start.checked_add(namesz)?)?;
+        let desc = data.get(desc_start..desc_start.checked_add(descsz)?)?;
+        let note = Note {
+            name: cstr_at(name, 0),
+            typ,
+            desc: desc.to_vec(),
+        };
+        Some((note, desc_start.checked_add(align4(descsz)?)?))
+    }
+
+    /// Hex string of the `.note.gnu.build-id` descriptor, taken from the
+    /// `PT_NOTE` segments when there are no sections.
+    pub fn build_id(&self) -> Option<String> {
+        self.sections
+            .iter()
+            .filter(|s| s.typ == SHT_NOTE)
+            .find_map(|s| self.read_build_id(s))
+            .or_else(|| {
+                self.notes()
+                    .into_iter()
+                    .find(|n| n.name == "GNU" && n.typ as u64 == NT_GNU_BUILD_ID)
+                    .map(|n| hex::encode(n.desc))
+            })
+    }
+
+    /// File name stored in `.gnu_debuglink`.
+    pub fn debuglink(&self) -> Option<String> {
+        let section = self.section(".gnu_debuglink")?;
+        let name = cstr_at(self.section_data(section)?, 0);
+        (!name.is_empty()).then_some(name)
+    }
+
+    /// Whether the file carries a full `.symtab` rather than only `.dynsym`.
+    pub fn has_symtab(&self) -> bool {
+        self.sections.iter().any(|s| s.typ == SHT_SYMTAB)
+    }
+
+    /// Symbols from `.symtab` followed by `.dynsym`.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut out = Vec::new();
+        for typ in [SHT_SYMTAB, SHT_DYNSYM] {
+            for section in self.sections.iter().filter(|s| s.typ == typ) {
+                out.extend(self.read_symbols(section).unwrap_or_default());
+            }
+        }
+        out
+    }
+
+    /// Looks a symbol up by name, ignoring `@VERSION` suffixes.
+    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+        self.symbols()
+            .into_iter()
+            .find(|s| s.value != 0 && s.name.split('@').next() == Some(name))
+    }
+
+    fn is_64(&self) -> bool {
+        self.arch == Arch::X64
+    }
+
+    fn word(&self, off: usize, size: usize) -> Option<u64> {
+        self.data.get(off..off + size).map(uk64)
+    }
+
+    fn addr(&self, off: usize) -> Option<u64> {
+        self.word(off, if self.is_64() { 8 } else { 4 })
+    }
+
+    fn read_sections(&self) -> Option<Vec<Section>> {
+        let (shoff, shentsize, shnum, shstrndx) = if self.is_64() {
+            (
+                self.word(0x28, 8)?,
+                self.word(0x3a, 2)?,
+                self.word(0x3c, 2)?,
+                self.word(0x3e, 2)?,
+            )
+        } else {
+            (
+                self.word(0x20, 4)?,
+                self.word(0x2e, 2)?,
+                self.word(0x30, 2)?,
+                self.word(0x32, 2)?,
+            )
+        };
+        let shoff = usize::try_from(shoff).ok()?;
+        let shentsize = shentsize as usize;
+
+        let mut sections = Vec::with_capacity(shnum as usize);
+        let mut name_offsets = Vec::with_capacity(shnum as usize);
+        for i in 0..shnum as usize {
+            let base = shoff + i * shentsize;
+            let section = if self.is_64() {
+                Section {
+                    name: String::new(),
+                    typ: self.word(base + 0x04, 4)? as u32,
+                    offset: self.word(base + 0x18, 8)?,
+                    size: self.word(base + 0x20, 8)?,
+                    link: self.word(base + 0x28, 4)? as u32,
+                    entsize: self.word(base + 0x38, 8)?,
+                }
+            } else {
+                Section {
+                    name: String::new(),
+                    typ: self.word(base + 0x04, 4)? as u32,
+                    offset: self.word(base + 0x10, 4)?,
+                    size: self.word(base + 0x14, 4)?,
+                    link: self.word(base + 0x18, 4)? as u32,
+                    entsize: self.word(base + 0x24, 4)?,
+                }
+            };
+            name_offsets.push(self.word(base, 4)? as usize);
+            sections.push(section);
+        }
+
+        if let Some(strtab) = sections.get(shstrndx as usize) {
+            let strtab_off = strtab.offset as usize;
+            for (section, name_off) in sections.iter_mut().zip(name_offsets) {
+                section.name = cstr_at(&self.data, strtab_off + name_off);
+            }
+        }
+        Some(sections)
+    }
+
+    fn read_segments(&self) -> Option<Vec<Segment>> {
+        let (phoff, phentsize, phnum) = if self.is_64() {
+            (self.word(0x20, 8)?, self.word(0x36, 2)?, self.word(0x38, 2)?)
+        } else {
+            (self.word(0x1c, 4)?, self.word(0x2a, 2)?, self.word(0x2c, 2)?)
+        };
+        let phoff = usize::try_from(phoff).ok()?;
+
+        let mut segments = Vec::with_capacity(phnum as usize);
+        for i in 0..phnum as usize {
+            let base = phoff + i * phentsize as usize;
+            let segment = if self.is_64() {
+                Segment {
+                    typ: self.word(base, 4)? as u32,
+                    flags: self.word(base + 0x04, 4)? as u32,
+                    offset: self.word(base + 0x08, 8)?,
+                    vaddr: self.word(base + 0x10, 8)?,
+                    filesz: self.word(base + 0x20, 8)?,
+                    memsz: self.word(base + 0x28, 8)?,
+                }
+            } else {
+                Segment {
+                    typ: self.word(base, 4)? as u32,
+                    offset: self.word(base + 0x04, 4)?,
+                    vaddr: self.word(base + 0x08, 4)?,
+                    filesz: self.word(base + 0x10, 4)?,
+                    memsz: self.word(base + 0x14, 4)?,
+                    flags: self.word(base + 0x18, 4)? as u32,
+                }
+            };
+            segments.push(segment);
+        }
+        Some(segments)
+    }
+
+    fn read_build_id(&self, note: &Section) -> Option<String> {
+        let data = self.section_data(note)?;
+        let align4 = |n: usize| (n + 3) & !3;
+        let mut off = 0;
+        while off + 12 <= data.len() {
+            let namesz = uk64(&data[off..off + 4]) as usize;
+            let descsz = uk64(&data[off + 4..off + 8]) as usize;
+            let typ = uk64(&data[off + 8..off + 12]);
+            let name_start = off + 12;
+            let desc_start = name_start + align4(namesz);
+            let desc = data.get(desc_start..desc_start + descsz)?;
+            if typ == NT_GNU_BUILD_ID && data.get(name_start..name_start + namesz)? == b"GNU\0" {
+                return Some(hex::encode(desc));
+            }
+            off = desc_start + align4(descsz);
+        }
+        None
+    }
+
+    fn read_symbols(&self, symtab: &Section) -> Option<Vec<Symbol>> {
+        let strtab = self.sections.get(symtab.link as usize)?;
+        let strtab_off = usize::try_from(strtab.offset).ok()?;
+        let entsize = match symtab.entsize {
+            0 if self.is_64() => 24,
+            0 => 16,
+            n => n as usize,
+        };
+        let start = usize::try_from(symtab.offset).ok()?;
+        let count = usize::try_from(symtab.size).ok()? / entsize;
+
+        let mut out = Vec::with_capacity(count);
+        for i in 0..count {
+            let base = start + i * entsize;
+            let name_off = self.word(base, 4)? as usize;
+            let (value, size) = if self.is_64() {
+                (self.word(base + 0x08, 8)?, self.word(base + 0x10, 8)?)
+            } else {
+                (self.addr(base + 0x04)?, self.word(base + 0x08, 4)?)
+            };
+            out.push(Symbol {
+                name: cstr_at(&self.data, strtab_off + name_off),
+                value,
+                size,
+            });
+        }
+        Some(out)
+    }
+}
+
+fn cstr_at(data: &[u8], off: usize) -> String {
+    let tail = data.get(off..).unwrap_or_default();
+    let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+    String::from_utf8_lossy(&tail[..end]).to_string()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
@@ -0,0 +1,171 @@
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::pid_t;
+
+use super::ptrace;
+
+/// How the target is kept still while it is captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreezeMethod {
+    /// PTRACE_SEIZE + PTRACE_INTERRUPT on every thread. If heapfinder dies,
+    /// the kernel detaches and the target runs on by itself.
+    Ptrace,
+    /// SIGSTOP to the whole process, SIGCONT afterwards.
+    Signal,
+}
+
+impl FreezeMethod {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ptrace" => Some(Self::Ptrace),
+            "sigstop" | "signal" => Some(Self::Signal),
+            _ => None,
+        }
+    }
+}
+
+/// Process that a SIGSTOP freeze must SIGCONT if heapfinder is killed.
+static STOPPED_PID: AtomicI32 = AtomicI32::new(0);
+
+const FATAL_SIGNALS: [i32; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+extern "C" fn resume_and_die(sig: i32) {
+    let pid = STOPPED_PID.swap(0, Ordering::SeqCst);
+    unsafe {
+        if pid != 0 {
+            libc::kill(pid, libc::SIGCONT);
+        }
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+/// Every thread of a live process, stopped until this is dropped. Threads
+/// that were already stopped stay stopped afterwards.
+#[derive(Debug)]
+pub struct Freeze {
+    pid: pid_t,
+    /// Threads seized by the ptrace method, with the signal to re-deliver.
+    seized: Vec<(pid_t, i32)>,
+    /// Whether the signal method sent SIGSTOP and owes a SIGCONT.
+    sent_stop: bool,
+}
+
+impl Freeze {
+    pub fn new(pid: u64, method: FreezeMethod) -> io::Result<Self> {
+        let mut freeze = Self {
+            pid: pid as pid_t,
+            seized: Vec::new(),
+            sent_stop: false,
+        };
+        // On error, dropping `freeze` undoes whatever was done so far.
+        match method {
+            FreezeMethod::Ptrace => freeze.seize_all()?,
+            FreezeMethod::Signal => freeze.stop_all()?,
+        }
+        Ok(freeze)
+    }
+
+    /// Seizes threads until a pass over `/proc/<pid>/task` finds no new
+    /// ones, so threads created meanwhile are caught too.
+    fn seize_all(&mut self) -> io::Result<()> {
+        loop {
+            let new: Vec<pid_t> = tasks(self.pid)?
+                .into_iter()
+                .filter(|tid| !self.seized.iter().any(|(t, _)| t == tid))
+                .collect();
+            if new.is_empty() {
+                return Ok(());
+            }
+            for tid in new {
+                match ptrace::seize_stop(tid, 0) {
+                    Ok(sig) => self.seized.push((tid, sig)),
+                    // It exited between the listing and the seize.
+                    Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                    Err(e) => {
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("Failed to stop thread {}: {}", tid, e),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn stop_all(&mut self) -> io::Result<()> {
+        if all_stopped(self.pid)? {
+            return Ok(());
+        }
+        STOPPED_PID.store(self.pid, Ordering::SeqCst);
+        for sig in FATAL_SIGNALS {
+            unsafe {
+                libc::signal(sig, resume_and_die as *const () as libc::sighandler_t);
+            }
+        }
+        if unsafe { libc::kill(self.pid, libc::SIGSTOP) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.sent_stop = true;
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !all_stopped(self.pid)? {
+            if Instant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("process {} did not stop", self.pid),
+                ));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Freeze {
+    fn drop(&mut self) {
+        for &(tid, sig) in &self.seized {
+            let _ = ptrace::detach_signal(tid, sig);
+        }
+        if self.sent_stop {
+            unsafe {
+                libc::kill(self.pid, libc::SIGCONT);
+            }
+            STOPPED_PID.store(0, Ordering::SeqCst);
+            for sig in FATAL_SIGNALS {
+                unsafe {
+                    libc::signal(sig, libc::SIG_DFL);
+                }
+            }
+        }
+    }
+}
+
+fn tasks(pid: pid_t) -> io::Result<Vec<pid_t>> {
+    let mut tids = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/task", pid))? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+            tids.push(tid);
+        }
+    }
+    Ok(tids)
+}
+
+/// Whether every thread is in `T` (stopped) or `t` (traced) state.
+fn all_stopped(pid: pid_t) -> io::Result<bool> {
+    for tid in tasks(pid)? {
+        let stat = match std::fs::read_to_string(format!("/proc/{}/task/{}/stat", pid, tid)) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        // The state follows the parenthesised command name.
+        let state = stat.rsplit_once(')').and_then(|(_, rest)| rest.trim_start().chars().next());
+        if !matches!(state, Some('T') | Some('t')) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,144 @@
+use std::ops::Range;
+
+use super::proc::{Maps, Proc};
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// One contiguous run of captured bytes.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub addr: u64,
+    pub data: Vec<u8>,
+}
+
+/// A thread as seen at capture time.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadPointer {
+    pub tid: u64,
+    /// `fs_base`, or the `%gs` base on i386.
+    pub thread_pointer: u64,
+}
+
+/// Mappings, the memory malloc uses and thread pointers of the target, read
+/// once. Every walker runs on this image and sees a single moment of the
+/// target.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryImage {
+    maps: Maps,
+    regions: Vec<Region>,
+    threads: Vec<ThreadPointer>,
+}
+
+impl MemoryImage {
+    pub fn new(maps: Maps, mut regions: Vec<Region>, threads: Vec<ThreadPointer>) -> Self {
+        regions.sort_by_key(|r| r.addr);
+        // Adjacent or overlapping reads become one region so reads may
+        // cross them.
+        let regions = regions.into_iter().fold(Vec::<Region>::new(), |mut acc, r| {
+            match acc.last_mut() {
+                Some(last) if last.addr + last.data.len() as u64 >= r.addr => {
+                    let overlap = last.addr + last.data.len() as u64 - r.addr;
+                    last.data.extend(r.data.into_iter().skip(overlap as usize))
+                }
+                _ => acc.push(r),
+            }
+            acc
+        });
+        Self {
+            maps,
+            regions,
+            threads,
+        }
+    }
+
+    /// Reads the maps, libc's writable data, `[heap]` and the thread pointer
+    /// of each thread. What else malloc uses is found from there and added
+    /// with `capture_ranges`.
+    pub fn capture(proc: &Proc) -> Self {
+        let mut image = Self::capture_memory(proc);
+        image.threads = proc
+            .tasks()
+            .into_iter()
+            .filter_map(|tid| {
+                Some(ThreadPointer {
+                    tid,
+                    thread_pointer: proc.thread_pointer(tid)?,
+                })
+            })
+            .collect();
+        image
+    }
+
+    /// Like `capture`, without stopping threads for their registers.
+    pub fn capture_memory(proc: &Proc) -> Self {
+        let maps = proc.maps();
+        let mut ranges = maps.libc_data_ranges();
+        ranges.extend(maps.ranges().remove("heap").unwrap_or_default());
+        let mut image = Self::new(maps, Vec::new(), Vec::new());
+        image.capture_ranges(proc, &ranges);
+        image
+    }
+
+    /// Adds `ranges` to the image in one batch. Pages of a range that
+    /// cannot be read as a whole are retried one by one.
+    pub fn capture_ranges(&mut self, proc: &Proc, ranges: &[Range<u64>]) {
+        let reads: Vec<(u64, usize)> = ranges
+            .iter()
+            .filter_map(|r| Some((r.start, usize::try_from(r.end.checked_sub(r.start)?).ok()?)))
+            .filter(|&(addr, size)| size != 0 && self.read(addr, size).is_none())
+            .collect();
+        if reads.is_empty() {
+            return;
+        }
+
+        let mut regions = std::mem::take(&mut self.regions);
+        for (&(addr, size), data) in reads.iter().zip(proc.read_many(&reads)) {
+            match data {
+                Some(data) => regions.push(Region { addr, data }),
+                None => regions.extend(Self::capture_pages(proc, addr, size)),
+            }
+        }
+        let maps = std::mem::take(&mut self.maps);
+        let threads = std::mem::take(&mut self.threads);
+        *self = Self::new(maps, regions, threads);
+    }
+
+    fn capture_pages(proc: &Proc, addr: u64, size: usize) -> Vec<Region> {
+        let pages: Vec<(u64, usize)> = (0..size)
+            .step_by(PAGE_SIZE)
+            .map(|off| (addr + off as u64, PAGE_SIZE.min(size - off)))
+            .collect();
+        pages
+            .iter()
+            .zip(proc.read_many(&pages))
+            .filter_map(|(&(addr, _), data)| Some(Region { addr, data: data? }))
+            .collect()
+    }
+
+    pub fn maps(&self) -> &Maps {
+        &self.maps
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn threads(&self) -> &[ThreadPointer] {
+        &self.threads
+    }
+
+    /// `size` bytes at `addr`, if they were all captured.
+    pub fn read(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        let i = self.regions.partition_point(|r| r.addr <= addr).checked_sub(1)?;
+        let region = &self.regions[i];
+        let start = usize::try_from(addr - region.addr).ok()?;
+        region.data.get(start..start.checked_add(size)?).map(<[u8]>::to_vec)
+    }
+
+    pub fn read_many(&self, ranges: &[(u64, usize)]) -> Vec<Option<Vec<u8>>> {
+        ranges
+            .iter()
+            .map(|&(addr, size)| self.read(addr, size))
+            .collect()
+    }
+}
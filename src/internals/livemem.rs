@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+use libc::{c_void, iovec};
+
+/// Most iovecs one `process_vm_readv` call accepts (UIO_MAXIOV).
+const IOV_MAX: usize = 1024;
+
+/// Memory of a live process. Reads go through `process_vm_readv`, which
+/// takes many ranges per syscall; `/proc/<pid>/mem`, opened once, covers
+/// kernels or policies that refuse it and pages it cannot read.
+#[derive(Debug)]
+pub struct LiveMem {
+    pid: u64,
+    vm_readv: bool,
+    mem: Option<File>,
+}
+
+impl LiveMem {
+    pub fn new(pid: u64) -> Self {
+        Self {
+            pid,
+            vm_readv: false,
+            mem: File::open(format!("/proc/{}/mem", pid)).ok(),
+        }
+    }
+
+    /// Picks the backend: `process_vm_readv` if it can read `addr`, an
+    /// address known to be mapped readable.
+    pub fn probe(&mut self, addr: u64) {
+        self.vm_readv = self.vm_read(&[(addr, 1)])[0].is_some();
+    }
+
+    pub fn read(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        self.read_many(&[(addr, size)]).pop().flatten()
+    }
+
+    /// Reads every `(addr, size)` range; a range that cannot be read
+    /// yields `None` without failing the others.
+    pub fn read_many(&self, ranges: &[(u64, usize)]) -> Vec<Option<Vec<u8>>> {
+        if !self.vm_readv {
+            return ranges
+                .iter()
+                .map(|&(addr, size)| self.read_file(addr, size))
+                .collect();
+        }
+        let mut out = self.vm_read(ranges);
+        for (slot, &(addr, size)) in out.iter_mut().zip(ranges) {
+            if slot.is_none() {
+                *slot = self.read_file(addr, size);
+            }
+        }
+        out
+    }
+
+    fn vm_read(&self, ranges: &[(u64, usize)]) -> Vec<Option<Vec<u8>>> {
+        let mut out = Vec::with_capacity(ranges.len());
+        let mut i = 0;
+        while i < ranges.len() {
+            let batch = &ranges[i..(i + IOV_MAX).min(ranges.len())];
+            let mut bufs: Vec<Vec<u8>> = batch.iter().map(|&(_, size)| vec![0u8; size]).collect();
+            let local: Vec<iovec> = bufs
+                .iter_mut()
+                .map(|b| iovec {
+                    iov_base: b.as_mut_ptr() as *mut c_void,
+                    iov_len: b.len(),
+                })
+                .collect();
+            let remote: Vec<iovec> = batch
+                .iter()
+                .map(|&(addr, size)| iovec {
+                    iov_base: addr as *mut c_void,
+                    iov_len: size,
+                })
+                .collect();
+            let ret = unsafe {
+                libc::process_vm_readv(
+                    self.pid as libc::pid_t,
+                    local.as_ptr(),
+                    local.len() as libc::c_ulong,
+                    remote.as_ptr(),
+                    remote.len() as libc::c_ulong,
+                    0,
+                )
+            };
+
+            // A partial transfer stops at the first range that failed; the
+            // ranges after it are retried in the next round.
+            let mut left = usize::try_from(ret).unwrap_or(0);
+            let mut complete = 0;
+            for buf in bufs {
+                if left < buf.len() {
+                    break;
+                }
+                left -= buf.len();
+                out.push(Some(buf));
+                complete += 1;
+            }
+            if complete < batch.len() {
+                out.push(None);
+                complete += 1;
+            }
+            i += complete;
+        }
+        out
+    }
+
+    fn read_file(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; size];
+        self.mem.as_ref()?.read_exact_at(&mut buf, addr).ok()?;
+        Some(buf)
+    }
+}
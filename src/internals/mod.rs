@@ -1,4 +1,17 @@
+pub mod calibrate;
+pub mod check;
 pub mod common;
 pub mod core;
+pub mod coredump;
+pub mod diff;
+pub mod elf;
+pub mod freeze;
+pub mod image;
+pub mod livemem;
 pub mod proc;
+pub mod profile;
+pub mod ptrace;
+pub mod resolve;
+pub mod snapfile;
 pub mod structs;
+pub mod trace;
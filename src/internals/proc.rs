@@ -1,30 +1,51 @@
 use hex;
 use regex::Regex;
-use std::fs::File;
 use std::ops::Range;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::io::{Read, Seek, SeekFrom};
+use std::sync::LazyLock;
 use super::common::{get_arch, Arch};
+use super::coredump::CoreDump;
+use super::livemem::LiveMem;
+use super::ptrace;
 
 const LIBC_REGEX: &str = r"^[^\x00]*libc(?:-[\d\.]+)?\.so(?:\.6)?$";
 const LD_REGEX: &str = r"^[^\x00]*ld(?:-[\d\.]+)?\.so(?:\.2)?$";
 
-#[derive(Debug)]
+static LIBC_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(LIBC_REGEX).unwrap());
+static LD_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(LD_REGEX).unwrap());
+static MAPS_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?<s>[0-9a-f]*)-(?<e>[0-9a-f]*) (?<p>[rwxps-]*)(?: [^ ]*){3} *(?<n>.*)").unwrap()
+});
+
+#[derive(Debug, Clone)]
 pub struct Map {
     range: Range<u64>,
     perm: String,
     mapname: String,
 }
 
+/// The target's mappings as read at one moment. Everything derived from
+/// them (ranges, bases, libc) is computed from this list, not re-read.
+#[derive(Debug, Clone, Default)]
+pub struct Maps(Vec<Map>);
+
+/// Where memory and mappings come from: a live process or a core file.
+#[derive(Debug)]
+enum Source {
+    Live(LiveMem),
+    Core(Box<CoreDump>),
+}
+
 #[derive(Debug)]
 pub struct Proc {
     pid: u64,
     arch: Arch,
+    source: Source,
 }
 
 impl Map {
-    fn new<P, M>(range: Range<u64>, perm: P, mapname: M) -> Self 
+    pub fn new<P, M>(range: Range<u64>, perm: P, mapname: M) -> Self 
     where 
         P: Into<String>,
         M: Into<String>, 
@@ -35,55 +56,42 @@ impl Map {
             mapname: mapname.into(),
         }
     }
-}
 
-impl Proc {
-    pub fn new(pid: u64) -> Self {
-        let path = format!("/proc/{}/exe", pid);
-        Proc { pid, arch: get_arch(&path).expect("Can't determine arch") }
+    pub fn range(&self) -> &Range<u64> {
+        &self.range
     }
 
-    pub fn arch(&self) -> Arch {
-        self.arch
+    pub fn perm(&self) -> &str {
+        &self.perm
     }
 
-    pub fn exe_path(&self) -> PathBuf {
-        PathBuf::from(format!("/proc/{}/exe", self.pid))
+    pub fn mapname(&self) -> &str {
+        &self.mapname
     }
 
-    pub fn libc_path(&self) -> Option<String> {
-        self.libc()
+    /// Key under which `ranges` and `bases` group this mapping.
+    fn key(&self) -> String {
+        match &*self.mapname {
+            "mapped" => "mapped".to_string(),
+            "[stack]" => "stack".to_string(),
+            "[heap]" => "heap".to_string(),
+            x if LIBC_RE.is_match(x) => "libc".to_string(),
+            other => Path::new(other)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        }
     }
+}
 
-    pub fn ld_path(&self) -> Option<String> {
-        self.ld()
+impl Maps {
+    pub fn new(maps: Vec<Map>) -> Self {
+        Maps(maps)
     }
 
-    pub fn vmmap(&self) -> Vec<Map> {
-        let mpath = format!("/proc/{}/maps", self.pid);
-        let re = Regex::new(
-            r"(?<s>[0-9a-f]*)-(?<e>[0-9a-f]*) (?<p>[rwxps-]*)(?: [^ ]*){3} *(?<n>.*)"
-        ).unwrap();
-        let mut contents = String::new();
-
-        File::open(&mpath)
-            .expect(&format!("Failed to open file: {}", mpath))
-            .read_to_string(&mut contents)
-            .unwrap();
-
-        re.captures_iter(&contents)
-            .map(|caps| {
-                let s = u64::from_str_radix(caps.name("s").unwrap().as_str(), 16).unwrap();
-                let e = u64::from_str_radix(caps.name("e").unwrap().as_str(), 16).unwrap();
-                let range = s..e;
-                let perm = caps.name("p").unwrap().as_str();
-                let mut name = caps.name("n").unwrap().as_str();
-                if name.is_empty() {
-                    name = "mapped";
-                }
-                Map::new(range, perm, name)
-        })
-        .collect()
+    pub fn iter(&self) -> std::slice::Iter<'_, Map> {
+        self.0.iter()
     }
 
     fn range_merge(vec: &mut Vec<Range<u64>>, mut new: Range<u64>) {
@@ -104,96 +112,308 @@ impl Proc {
     }
 
     pub fn ranges(&self) -> HashMap<String, Vec<Range<u64>>> {
-        let libc_re = Regex::new(LIBC_REGEX).unwrap();
         let mut ranges: HashMap<String, Vec<Range<u64>>> = 
             ["mapped", "libc", "heap", "stack"]
                 .map(|k| (k.to_string(), Vec::new())).into();
 
-        for m in self.vmmap() {
-            let key = match &*m.mapname {
-                "mapped" => "mapped".to_string(),
-                "[stack]" => "stack".to_string(),
-                "[heap]" => "heap".to_string(),
-                x if libc_re.is_match(x) => "libc".to_string(),
-                other => Path::new(other)
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string(),
-            };
-
+        for m in self.iter() {
             ranges
-                .entry(key)
+                .entry(m.key())
                 .and_modify(|v| Self::range_merge(v, m.range.clone()))
-                .or_insert(vec![m.range]);   
+                .or_insert(vec![m.range.clone()]);   
         }
         ranges
     }
 
     pub fn bases(&self) -> HashMap<String, Vec<u64>> {
-        let libc_re = Regex::new(LIBC_REGEX).unwrap();
         let mut bases: HashMap<String, Vec<u64>> = 
             ["mapped", "libc", "heap", "stack"]
                 .map(|k| (k.to_string(), Vec::new())).into();
 
-        for m in self.vmmap() {
-            let key = match &*m.mapname {
-                "mapped" => "mapped".to_string(),
-                "[stack]" => "stack".to_string(),
-                "[heap]" => "heap".to_string(),
-                x if libc_re.is_match(x) => "libc".to_string(),
-                other => Path::new(other)
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string(),
-            };
-
+        for m in self.iter() {
             bases
-                .entry(key)
+                .entry(m.key())
                 .and_modify(|v| v.push(m.range.start))
                 .or_insert(vec![m.range.start]);
         }
         bases
     }
 
+    #[allow(dead_code)]
     pub fn whereis(&self, addr: u64) -> Option<String> {
-        let libc_re = Regex::new(LIBC_REGEX).unwrap();
+        self.iter().find(|m| m.range.contains(&addr)).map(Map::key)
+    }
 
-        for m in self.vmmap() {
-            if !m.range.contains(&addr) {
-                continue;
+    /// The file mapped at `addr` and the offset of `addr` from the lowest
+    /// mapping of that file.
+    pub fn locate(&self, addr: u64) -> Option<(String, u64)> {
+        let map = self.iter().find(|m| m.range.contains(&addr))?;
+        let base = self
+            .iter()
+            .filter(|m| m.mapname == map.mapname)
+            .map(|m| m.range.start)
+            .min()?;
+        Some((map.mapname.clone(), addr - base))
+    }
+
+    /// Writable mappings of libc, plus the anonymous mapping that carries
+    /// the tail of its `.bss` when it directly follows them.
+    pub fn libc_data_ranges(&self) -> Vec<Range<u64>> {
+        let mut result: Vec<Range<u64>> = Vec::new();
+        let mut prev_libc_end = None;
+
+        for m in self.iter() {
+            let is_libc = LIBC_RE.is_match(&m.mapname);
+            let follows_libc = prev_libc_end == Some(m.range.start) && m.mapname == "mapped";
+            if m.perm.contains('w') && (is_libc || follows_libc) {
+                result.push(m.range.clone());
             }
+            prev_libc_end = if is_libc { Some(m.range.end) } else { None };
+        }
+        result
+    }
 
-            let name = m.mapname.as_str();
-
-            return Some(match name {
-                "mapped" => "mapped".to_string(),
-                "[stack]" => "stack".to_string(),
-                "[heap]" => "heap".to_string(),
-                x if libc_re.is_match(x) => "libc".to_string(),
-                other => Path::new(other)
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string(),
-            });
+    /// Writable anonymous mappings, as listed (not merged).
+    pub fn anon_ranges(&self) -> Vec<Range<u64>> {
+        self.iter()
+            .filter(|m| m.mapname == "mapped" && m.perm.starts_with("rw"))
+            .map(|m| m.range.clone())
+            .collect()
+    }
+
+    /// Lowest address of `path` once one of its mappings is executable.
+    pub fn loaded_base(&self, path: &str) -> Option<u64> {
+        let maps: Vec<&Map> = self.iter().filter(|m| m.mapname == path).collect();
+        if !maps.iter().any(|m| m.perm.contains('x')) {
+            return None;
         }
-        None
+        maps.iter().map(|m| m.range.start).min()
     }
 
-    pub fn read(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
-        let path = format!("/proc/{}/mem", self.pid);
-        let mut buf = vec![0u8; size];
+    pub fn libc(&self) -> Option<String> {
+        self.iter()
+            .find(|m| LIBC_RE.is_match(&m.mapname))
+            .map(|m| m.mapname.clone())
+    }
 
-        if let Ok(mut f) = File::open(&path) {
-            if f.seek(SeekFrom::Start(addr)).is_ok() {
-                if f.read_exact(&mut buf).is_ok() {
-                    return Some(buf);
-                }
+    pub fn ld(&self) -> Option<String> {
+        self.iter()
+            .find(|m| LD_RE.is_match(&m.mapname))
+            .map(|m| m.mapname.clone())
+    }
+}
+
+impl Proc {
+    pub fn new(pid: u64) -> Self {
+        let path = format!("/proc/{}/exe", pid);
+        let mut proc = Proc {
+            pid,
+            arch: get_arch(&path).expect("Can't determine arch"),
+            source: Source::Live(LiveMem::new(pid)),
+        };
+        let probe = proc
+            .vmmap()
+            .into_iter()
+            .find(|m| m.perm.starts_with('r'))
+            .map(|m| m.range.start);
+        if let (Source::Live(mem), Some(addr)) = (&mut proc.source, probe) {
+            mem.probe(addr);
+        }
+        proc
+    }
+
+    /// A process as recorded in the core file at `path`. `libc` replaces
+    /// the libc named in the core when reading pages that were not dumped.
+    pub fn from_core(path: &str, libc: Option<&str>) -> std::io::Result<Self> {
+        let mut core = CoreDump::open(path)?;
+        if let Some(libc) = libc {
+            let originals: Vec<String> = core
+                .maps()
+                .into_iter()
+                .map(|(_, _, name)| name)
+                .filter(|name| LIBC_RE.is_match(name))
+                .collect();
+            for original in originals {
+                core.substitute(&original, libc);
             }
         }
-        None
+        Ok(Proc { pid: core.pid(), arch: core.arch(), source: Source::Core(Box::new(core)) })
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    pub fn is_core(&self) -> bool {
+        matches!(self.source, Source::Core(_))
+    }
+
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// Whether the live process still exists and is not a zombie. Always
+    /// false for core files.
+    pub fn is_alive(&self) -> bool {
+        if self.is_core() {
+            return false;
+        }
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", self.pid)) else {
+            return false;
+        };
+        let state = stat.rsplit_once(')').and_then(|(_, rest)| rest.trim_start().chars().next());
+        !matches!(state, Some('Z') | Some('X') | None)
+    }
+
+    pub fn exe_path(&self) -> PathBuf {
+        match &self.source {
+            Source::Live(_) => PathBuf::from(format!("/proc/{}/exe", self.pid)),
+            Source::Core(core) => PathBuf::from(core.exe_path().unwrap_or_default()),
+        }
+    }
+
+    pub fn libc_path(&self) -> Option<String> {
+        self.libc()
+    }
+
+    #[allow(dead_code)]
+    pub fn ld_path(&self) -> Option<String> {
+        self.ld()
+    }
+
+    /// Thread ids from `/proc/<pid>/task`, main thread first.
+    pub fn tasks(&self) -> Vec<u64> {
+        if let Source::Core(core) = &self.source {
+            return core.threads().iter().map(|t| t.tid).collect();
+        }
+        let mut tids: Vec<u64> = std::fs::read_dir(format!("/proc/{}/task", self.pid))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        tids.sort_unstable();
+        tids
+    }
+
+    /// Thread pointer of `tid` (`fs_base`, or the `%gs` base on i386).
+    /// A live thread is briefly stopped with ptrace to read it, unless it
+    /// is already stopped under our ptrace.
+    pub fn thread_pointer(&self, tid: u64) -> Option<u64> {
+        if let Source::Core(core) = &self.source {
+            return core.threads().iter().find(|t| t.tid == tid).map(|t| t.thread_pointer);
+        }
+        let regs = ptrace::getregs(tid as i32)
+            .or_else(|_| ptrace::seize_regs(tid as i32))
+            .ok()?;
+        Some(match self.arch {
+            Arch::X86 => regs.gs_base,
+            Arch::X64 => regs.fs_base,
+        })
+    }
+
+    /// Value of `name` in the target's initial environment. Not available
+    /// for core files.
+    pub fn env(&self, name: &str) -> Option<String> {
+        if self.is_core() {
+            return None;
+        }
+        let data = std::fs::read(format!("/proc/{}/environ", self.pid)).ok()?;
+        data.split(|&b| b == 0).find_map(|var| {
+            let var = String::from_utf8_lossy(var);
+            let (key, value) = var.split_once('=')?;
+            (key == name).then(|| value.to_string())
+        })
+    }
+
+    /// Value of a glibc tunable such as `glibc.malloc.tcache_count`, as set
+    /// through `GLIBC_TUNABLES`.
+    pub fn tunable(&self, name: &str) -> Option<String> {
+        let tunables = self.env("GLIBC_TUNABLES")?;
+        // A tunable given twice takes its last value.
+        tunables
+            .rsplit(':')
+            .filter_map(|t| t.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    pub fn vmmap(&self) -> Vec<Map> {
+        if let Source::Core(core) = &self.source {
+            return core
+                .maps()
+                .into_iter()
+                .map(|(range, perm, name)| {
+                    let name = if name.is_empty() { "mapped".to_string() } else { name };
+                    Map::new(range, perm, name)
+                })
+                .collect();
+        }
+        // Empty once the process has exited.
+        let mpath = format!("/proc/{}/maps", self.pid);
+        let Ok(contents) = std::fs::read_to_string(&mpath) else {
+            return Vec::new();
+        };
+
+        MAPS_RE.captures_iter(&contents)
+            .map(|caps| {
+                let s = u64::from_str_radix(caps.name("s").unwrap().as_str(), 16).unwrap();
+                let e = u64::from_str_radix(caps.name("e").unwrap().as_str(), 16).unwrap();
+                let range = s..e;
+                let perm = caps.name("p").unwrap().as_str();
+                let mut name = caps.name("n").unwrap().as_str();
+                if name.is_empty() {
+                    name = "mapped";
+                }
+                Map::new(range, perm, name)
+        })
+        .collect()
+    }
+
+    /// Reads `/proc/<pid>/maps` (or the core's mappings) once.
+    pub fn maps(&self) -> Maps {
+        Maps(self.vmmap())
+    }
+
+    pub fn ranges(&self) -> HashMap<String, Vec<Range<u64>>> {
+        self.maps().ranges()
+    }
+
+    #[allow(dead_code)]
+    pub fn bases(&self) -> HashMap<String, Vec<u64>> {
+        self.maps().bases()
+    }
+
+    #[allow(dead_code)]
+    pub fn whereis(&self, addr: u64) -> Option<String> {
+        self.maps().whereis(addr)
+    }
+
+    pub fn libc_data_ranges(&self) -> Vec<Range<u64>> {
+        self.maps().libc_data_ranges()
+    }
+
+    pub fn loaded_base(&self, path: &str) -> Option<u64> {
+        self.maps().loaded_base(path)
+    }
+
+    pub fn read(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        match &self.source {
+            Source::Live(mem) => mem.read(addr, size),
+            Source::Core(core) => core.read(addr, size),
+        }
+    }
+
+    /// Reads several `(addr, size)` ranges at once, in as few syscalls as
+    /// the backend allows. Unreadable ranges come back as `None`.
+    pub fn read_many(&self, ranges: &[(u64, usize)]) -> Vec<Option<Vec<u8>>> {
+        match &self.source {
+            Source::Live(mem) => mem.read_many(ranges),
+            Source::Core(core) => ranges
+                .iter()
+                .map(|&(addr, size)| core.read(addr, size))
+                .collect(),
+        }
     }
 
     fn searchmem(&self, range: &Range<u64>, pattern: &str) -> Vec<(u64, String)> {
@@ -217,15 +437,12 @@ impl Proc {
             None => return result,
         };
 
-        let needle: Vec<u8> = if pattern.starts_with("0x") {
-            let mut hexstr = &pattern[2..];
-            if hexstr.len() % 2 != 0 {
-                let mut s = String::with_capacity(hexstr.len() + 1);
-                s.push('0');
-                s.push_str(hexstr);
-                hexstr = Box::leak(s.into_boxed_str());
+        let needle: Vec<u8> = if let Some(digits) = pattern.strip_prefix("0x") {
+            let mut hexstr = digits.to_string();
+            if !hexstr.len().is_multiple_of(2) {
+                hexstr.insert(0, '0');
             }
-            let mut b = match hex::decode(hexstr) {
+            let mut b = match hex::decode(&hexstr) {
                 Ok(v) => v,
                 Err(_) => return result,
             };
@@ -286,32 +503,22 @@ impl Proc {
         }
     }
 
+    #[allow(dead_code)]
     pub fn search_in_stack(&self, search: &str) -> Vec<(u64, String)> {
         self.searchmem_by_mapname("[stack]", search)
     }
 
+    #[allow(dead_code)]
     pub fn search_in_heap(&self, search: &str) -> Vec<(u64, String)> {
         self.searchmem_by_mapname("[heap]", search)
     }
 
     fn libc(&self) -> Option<String> {
-        let re = Regex::new(LIBC_REGEX).ok()?;
-        for m in self.vmmap() {
-            if re.is_match(&m.mapname) {
-                return Some(m.mapname.clone());
-            }
-        };
-        None
+        self.maps().libc()
     }
 
+    #[allow(dead_code)]
     fn ld(&self) -> Option<String> {
-        let re = Regex::new(LD_REGEX).ok()?;
-        for m in self.vmmap() {
-            if re.is_match(&m.mapname) {
-                return Some(m.mapname.clone());
-            }
-        };
-        None
+        self.maps().ld()
     }
-
 }
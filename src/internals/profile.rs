@@ -0,0 +1,254 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::common::parse_u64;
+use super::elf::Elf;
+
+/// Everything heapfinder needs to know about one libc build. Only the
+/// version and `main_arena` are required; the rest is filled in when
+/// calibration or debug symbols can provide it.
+#[derive(Debug, Clone, Default)]
+pub struct LibcProfile {
+    pub build_id: Option<String>,
+    pub libc_version: String,
+    pub main_arena_offset: u64,
+    pub mp_offset: Option<u64>,
+    pub global_max_fast_offset: Option<u64>,
+    pub io_list_all_offset: Option<u64>,
+    pub tcache_enable: bool,
+    pub tcache_bins: Option<u64>,
+    pub tcache_count: Option<u64>,
+}
+
+/// Directory of `<key>.json` profiles. The key is the libc build-id, or a
+/// hash of the file contents for libcs built without one.
+pub fn profile_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("HEAPFINDER_PROFILES") {
+        return PathBuf::from(dir);
+    }
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+    base.join("heapfinder").join("profiles")
+}
+
+/// Profile key for the libc at `libc_path`.
+pub fn libc_key(libc_path: &str) -> io::Result<String> {
+    let data = fs::read(libc_path)?;
+    let key = Elf::parse(data.clone())
+        .ok()
+        .and_then(|elf| elf.build_id())
+        .unwrap_or_else(|| format!("{:016x}", fnv1a64(&data)));
+    Ok(key)
+}
+
+pub fn load(key: &str) -> Option<LibcProfile> {
+    let text = fs::read_to_string(profile_dir().join(format!("{}.json", key))).ok()?;
+    parse_profile(&text)
+}
+
+pub fn load_for(libc_path: &str) -> Option<LibcProfile> {
+    load(&libc_key(libc_path).ok()?)
+}
+
+pub fn store(key: &str, profile: &LibcProfile) -> io::Result<PathBuf> {
+    let dir = profile_dir();
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.json", key));
+    fs::write(&path, profile_json(profile))?;
+    Ok(path)
+}
+
+/// All stored profiles with their keys, sorted by key.
+pub fn list() -> Vec<(String, LibcProfile)> {
+    let mut out: Vec<(String, LibcProfile)> = fs::read_dir(profile_dir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let key = path.file_stem()?.to_string_lossy().to_string();
+            let profile = parse_profile(&fs::read_to_string(&path).ok()?)?;
+            Some((key, profile))
+        })
+        .collect();
+    out.sort_by(|a, b| a.0.cmp(&b.0));
+    out
+}
+
+pub fn profile_json(profile: &LibcProfile) -> String {
+    let mut fields = Vec::new();
+    if let Some(id) = &profile.build_id {
+        fields.push(format!("\"build_id\": {}", json_string(id)));
+    }
+    fields.push(format!("\"libc_version\": {}", json_string(&profile.libc_version)));
+    fields.push(format!("\"main_arena_offset\": \"0x{:x}\"", profile.main_arena_offset));
+    let offsets = [
+        ("mp_offset", profile.mp_offset),
+        ("global_max_fast_offset", profile.global_max_fast_offset),
+        ("io_list_all_offset", profile.io_list_all_offset),
+    ];
+    for (name, value) in offsets {
+        if let Some(v) = value {
+            fields.push(format!("\"{}\": \"0x{:x}\"", name, v));
+        }
+    }
+    fields.push(format!("\"tcache_enable\": {}", profile.tcache_enable));
+    for (name, value) in [("tcache_bins", profile.tcache_bins), ("tcache_count", profile.tcache_count)] {
+        if let Some(v) = value {
+            fields.push(format!("\"{}\": {}", name, v));
+        }
+    }
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
+
+/// Accepts our own profiles as well as the JSON printed by
+/// `libc/libc_info.c`, which leaves the version and offset unquoted.
+pub fn parse_profile(text: &str) -> Option<LibcProfile> {
+    let offset = |key| json_field(text, key).as_deref().and_then(parse_u64);
+    Some(LibcProfile {
+        build_id: json_field(text, "build_id"),
+        libc_version: json_field(text, "libc_version")?,
+        main_arena_offset: offset("main_arena_offset")?,
+        mp_offset: offset("mp_offset"),
+        global_max_fast_offset: offset("global_max_fast_offset"),
+        io_list_all_offset: offset("io_list_all_offset"),
+        tcache_enable: json_field(text, "tcache_enable")? == "true",
+        tcache_bins: offset("tcache_bins"),
+        tcache_count: offset("tcache_count"),
+    })
+}
+
+/// Value of `key` in a flat JSON object, without surrounding quotes and
+/// with string escapes decoded. Only keys are matched, never text inside
+/// string values.
+pub fn json_field(text: &str, key: &str) -> Option<String> {
+    let mut rest = text.trim_start().strip_prefix('{')?;
+    loop {
+        let (name, after) = split_string(rest.trim_start())?;
+        let value = after.trim_start().strip_prefix(':')?.trim_start();
+        let (value, after) = if value.starts_with('"') {
+            split_string(value)?
+        } else {
+            let end = value.find([',', '}']).unwrap_or(value.len());
+            (value[..end].trim().to_string(), &value[end..])
+        };
+        if name == key {
+            return Some(value);
+        }
+        rest = after.trim_start().strip_prefix(',')?;
+    }
+}
+
+/// `value` as a quoted JSON string.
+pub fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Decodes the JSON string that `text` starts with, ignoring anything
+/// after its closing quote.
+pub fn json_unquote(text: &str) -> Option<String> {
+    split_string(text).map(|(value, _)| value)
+}
+
+/// The decoded JSON string that `text` starts with and the text after its
+/// closing quote.
+fn split_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.chars();
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some((out, chars.as_str())),
+            '\\' => out.push(match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                }
+                c => c,
+            }),
+            c => out.push(c),
+        }
+    }
+}
+
+fn fnv1a64(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let profile = LibcProfile {
+            build_id: Some("4f536ac1cd2e8806aed8556ea7795c47404de8a9".to_string()),
+            libc_version: "2.35 \"custom\" build".to_string(),
+            main_arena_offset: 0x21ac80,
+            mp_offset: Some(0x21a360),
+            global_max_fast_offset: None,
+            io_list_all_offset: Some(0x21b680),
+            tcache_enable: true,
+            tcache_bins: Some(64),
+            tcache_count: Some(7),
+        };
+        let parsed = parse_profile(&profile_json(&profile)).unwrap();
+        assert_eq!(parsed.build_id, profile.build_id);
+        assert_eq!(parsed.libc_version, profile.libc_version);
+        assert_eq!(parsed.main_arena_offset, profile.main_arena_offset);
+        assert_eq!(parsed.mp_offset, profile.mp_offset);
+        assert_eq!(parsed.global_max_fast_offset, None);
+        assert_eq!(parsed.io_list_all_offset, profile.io_list_all_offset);
+        assert!(parsed.tcache_enable);
+        assert_eq!(parsed.tcache_bins, Some(64));
+        assert_eq!(parsed.tcache_count, Some(7));
+    }
+
+    #[test]
+    fn libc_info_output() {
+        let text =
+            "{\"libc_version\": 2.35,\"main_arena_offset\": 0x21ac80,\"tcache_enable\": true}\n";
+        let parsed = parse_profile(text).unwrap();
+        assert_eq!(parsed.libc_version, "2.35");
+        assert_eq!(parsed.main_arena_offset, 0x21ac80);
+        assert!(parsed.tcache_enable);
+        assert_eq!(parsed.build_id, None);
+        assert_eq!(parsed.mp_offset, None);
+    }
+
+    #[test]
+    fn keys_inside_values_are_skipped() {
+        // The escaped quote and the closing one make `"mp_offset"` appear
+        // inside the version string.
+        let text = r#"{"libc_version": "2.35\"mp_offset", "main_arena_offset": "0x10", "mp_offset": "0x20", "tcache_enable": false}"#;
+        let parsed = parse_profile(text).unwrap();
+        assert_eq!(parsed.libc_version, "2.35\"mp_offset");
+        assert_eq!(parsed.mp_offset, Some(0x20));
+    }
+}
@@ -0,0 +1,268 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::ptr;
+
+use libc::{c_void, pid_t, user_regs_struct};
+
+fn check(ret: libc::c_long) -> io::Result<libc::c_long> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Forks and execs `argv` with PTRACE_TRACEME. Returns once the child is
+/// stopped at the exec trap.
+pub fn spawn_traced(argv: &[&str]) -> io::Result<pid_t> {
+    let args = argv
+        .iter()
+        .map(|a| CString::new(*a).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut ptrs: Vec<*const libc::c_char> = args.iter().map(|a| a.as_ptr()).collect();
+    ptrs.push(ptr::null());
+
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        unsafe {
+            libc::ptrace(libc::PTRACE_TRACEME, 0, ptr::null_mut::<c_void>(), ptr::null_mut::<c_void>());
+            libc::execv(ptrs[0], ptrs.as_ptr());
+            libc::_exit(127);
+        }
+    }
+
+    match wait_stop(pid)? {
+        Some(libc::SIGTRAP) => Ok(pid),
+        _ => Err(io::Error::other(format!("Failed to exec {}", argv[0]))),
+    }
+}
+
+/// Waits for `pid` to stop. Returns the stop signal, or `None` if it exited.
+pub fn wait_stop(pid: pid_t) -> io::Result<Option<i32>> {
+    let mut status = 0;
+    loop {
+        let ret = unsafe { libc::waitpid(pid, &mut status, libc::__WALL) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if libc::WIFSTOPPED(status) {
+            return Ok(Some(libc::WSTOPSIG(status)));
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            return Ok(None);
+        }
+    }
+}
+
+pub fn cont(pid: pid_t, sig: i32) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_CONT, pid, ptr::null_mut::<c_void>(), sig as usize as *mut c_void)
+    })?;
+    Ok(())
+}
+
+/// Resumes until the next syscall entry or exit.
+pub fn syscall(pid: pid_t) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_SYSCALL, pid, ptr::null_mut::<c_void>(), ptr::null_mut::<c_void>())
+    })?;
+    Ok(())
+}
+
+pub fn kill(pid: pid_t) {
+    unsafe {
+        libc::kill(pid, libc::SIGKILL);
+    }
+    let _ = wait_stop(pid);
+}
+
+pub fn getregs(pid: pid_t) -> io::Result<user_regs_struct> {
+    let mut regs: user_regs_struct = unsafe { mem::zeroed() };
+    check(unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETREGS,
+            pid,
+            ptr::null_mut::<c_void>(),
+            &mut regs as *mut user_regs_struct as *mut c_void,
+        )
+    })?;
+    Ok(regs)
+}
+
+pub fn setregs(pid: pid_t, regs: &user_regs_struct) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(
+            libc::PTRACE_SETREGS,
+            pid,
+            ptr::null_mut::<c_void>(),
+            regs as *const user_regs_struct as *mut c_void,
+        )
+    })?;
+    Ok(())
+}
+
+pub fn peek(pid: pid_t, addr: u64) -> io::Result<u64> {
+    // PEEKDATA returns the word itself, so -1 is only an error with errno set.
+    unsafe { *libc::__errno_location() = 0 };
+    let ret = unsafe {
+        libc::ptrace(libc::PTRACE_PEEKDATA, pid, addr as *mut c_void, ptr::null_mut::<c_void>())
+    };
+    if ret == -1 && unsafe { *libc::__errno_location() } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as u64)
+}
+
+pub fn poke(pid: pid_t, addr: u64, val: u64) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_POKEDATA, pid, addr as *mut c_void, val as *mut c_void)
+    })?;
+    Ok(())
+}
+
+pub fn detach(pid: pid_t) -> io::Result<()> {
+    detach_signal(pid, 0)
+}
+
+/// Detaches and lets the tracee continue with `sig` (0 for none).
+pub fn detach_signal(pid: pid_t, sig: i32) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_DETACH, pid, ptr::null_mut::<c_void>(), sig as usize as *mut c_void)
+    })?;
+    Ok(())
+}
+
+/// Seizes `tid` with `PTRACE_O_*` `options` and stops it with
+/// PTRACE_INTERRUPT. Returns the signal it stopped for instead when one
+/// arrived first; pass it to `detach_signal` so it is not lost. A thread
+/// already in group-stop stays stopped after the detach.
+pub fn seize_stop(tid: pid_t, options: i32) -> io::Result<i32> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_SEIZE, tid, ptr::null_mut::<c_void>(), options as usize as *mut c_void)
+    })?;
+    let stopped = check(unsafe {
+        libc::ptrace(libc::PTRACE_INTERRUPT, tid, ptr::null_mut::<c_void>(), ptr::null_mut::<c_void>())
+    })
+    .and_then(|_| wait_event_stop(tid));
+    if stopped.is_err() {
+        let _ = detach(tid);
+    }
+    stopped
+}
+
+/// Waits for a seized thread to stop and tells a PTRACE_EVENT_STOP (0)
+/// from a signal-delivery-stop (the signal).
+fn wait_event_stop(tid: pid_t) -> io::Result<i32> {
+    let mut status = 0;
+    loop {
+        let ret = unsafe { libc::waitpid(tid, &mut status, libc::__WALL) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if !libc::WIFSTOPPED(status) {
+            return Err(io::Error::other(format!("thread {} exited", tid)));
+        }
+        if status >> 16 == libc::PTRACE_EVENT_STOP {
+            return Ok(0);
+        }
+        return Ok(libc::WSTOPSIG(status));
+    }
+}
+
+/// Registers of a thread we are not tracing: seizes and interrupts it,
+/// reads the registers and lets it run again.
+pub fn seize_regs(tid: pid_t) -> io::Result<user_regs_struct> {
+    let sig = seize_stop(tid, 0)?;
+    let regs = getregs(tid);
+    detach_signal(tid, sig)?;
+    regs
+}
+
+/// Asks a seized thread to stop; the stop is reported by `wait_any`.
+pub fn interrupt(tid: pid_t) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_INTERRUPT, tid, ptr::null_mut::<c_void>(), ptr::null_mut::<c_void>())
+    })?;
+    Ok(())
+}
+
+/// Keeps a seized thread in group-stop while still reporting events.
+pub fn listen(tid: pid_t) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_LISTEN, tid, ptr::null_mut::<c_void>(), ptr::null_mut::<c_void>())
+    })?;
+    Ok(())
+}
+
+pub fn singlestep(tid: pid_t, sig: i32) -> io::Result<()> {
+    check(unsafe {
+        libc::ptrace(libc::PTRACE_SINGLESTEP, tid, ptr::null_mut::<c_void>(), sig as usize as *mut c_void)
+    })?;
+    Ok(())
+}
+
+/// Waits for any traced thread (`-1`) or for `tid`, and returns it with
+/// its raw wait status. Unlike `wait_stop`, an interrupting signal is
+/// returned as `ErrorKind::Interrupted`.
+pub fn wait_raw(tid: pid_t) -> io::Result<(pid_t, i32)> {
+    let mut status = 0;
+    let ret = unsafe { libc::waitpid(tid, &mut status, libc::__WALL) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((ret, status))
+}
+
+/// Writes one byte at `addr` and returns the byte it replaced.
+pub fn poke_byte(pid: pid_t, addr: u64, byte: u8) -> io::Result<u8> {
+    let word = peek(pid, addr)?;
+    poke(pid, addr, (word & !0xff) | byte as u64)?;
+    Ok(word as u8)
+}
+
+/// Replaces the low byte at `addr` with `int3`, returning the original word.
+pub fn set_breakpoint(pid: pid_t, addr: u64) -> io::Result<u64> {
+    let orig = peek(pid, addr)?;
+    poke(pid, addr, (orig & !0xff) | 0xcc)?;
+    Ok(orig)
+}
+
+/// Calls `func(args..)` inside a stopped tracee and returns `rax`.
+/// `ret_addr` must hold an `int3` so the call traps on return.
+pub fn remote_call(pid: pid_t, func: u64, args: &[u64], ret_addr: u64) -> io::Result<u64> {
+    let saved = getregs(pid)?;
+    let mut regs = saved;
+
+    let sp = (saved.rsp - 0x200) & !0xf;
+    poke(pid, sp - 8, ret_addr)?;
+    regs.rsp = sp - 8;
+    regs.rip = func;
+    regs.rax = 0;
+    let slots = [&mut regs.rdi, &mut regs.rsi, &mut regs.rdx, &mut regs.rcx];
+    for (slot, arg) in slots.into_iter().zip(args) {
+        *slot = *arg;
+    }
+    setregs(pid, &regs)?;
+    cont(pid, 0)?;
+
+    match wait_stop(pid)? {
+        Some(libc::SIGTRAP) => {}
+        Some(sig) => return Err(io::Error::other(format!("Remote call stopped by signal {}", sig))),
+        None => return Err(io::Error::other("Process exited during remote call")),
+    }
+    let ret = getregs(pid)?.rax;
+    setregs(pid, &saved)?;
+    Ok(ret)
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::common::uk64;
+use super::elf::Elf;
+use super::proc::Proc;
+use super::structs::CStructDef;
+
+/// glibc's NBINS: bin 0 is unused, so `bins` holds NBINS - 1 fd/bk pairs.
+const NBINS: usize = 128;
+
+const DEFAULT_DEBUG_DIR: &str = "/usr/lib/debug";
+
+/// Offsets of malloc internals taken from libc's symbol tables. `tcache` is
+/// a TLS symbol, so its value is relative to libc's TLS block.
+#[derive(Debug, Clone, Default)]
+pub struct LibcSymbols {
+    pub main_arena: Option<u64>,
+    pub mp: Option<u64>,
+    pub global_max_fast: Option<u64>,
+    pub tcache: Option<u64>,
+    pub thread_arena: Option<u64>,
+    pub io_list_all: Option<u64>,
+    pub io_2_1_stdout: Option<u64>,
+    pub debug_file: Option<PathBuf>,
+}
+
+impl LibcSymbols {
+    /// Reads the symbols of `libc_path` itself and, when it is stripped, of
+    /// its separate debug file under `debug_dir` or `/usr/lib/debug`.
+    pub fn load(libc_path: &str, debug_dir: Option<&Path>) -> Self {
+        let libc = match Elf::open(libc_path) {
+            Ok(elf) => elf,
+            Err(_) => return Self::default(),
+        };
+        let mut symbols = Self::from_elf(&libc);
+        if libc.has_symtab() {
+            return symbols;
+        }
+
+        let mut dirs: Vec<&Path> = debug_dir.into_iter().collect();
+        dirs.push(Path::new(DEFAULT_DEBUG_DIR));
+        let debug = dirs
+            .iter()
+            .flat_map(|dir| debug_file_candidates(&libc, libc_path, dir))
+            .find_map(|path| Some((Elf::open(path.to_str()?).ok()?, path)));
+
+        // The debug file's full symtab wins over the libc's dynsym.
+        if let Some((elf, path)) = debug {
+            let extra = Self::from_elf(&elf);
+            symbols = Self {
+                main_arena: extra.main_arena.or(symbols.main_arena),
+                mp: extra.mp.or(symbols.mp),
+                global_max_fast: extra.global_max_fast.or(symbols.global_max_fast),
+                tcache: extra.tcache.or(symbols.tcache),
+                thread_arena: extra.thread_arena.or(symbols.thread_arena),
+                io_list_all: extra.io_list_all.or(symbols.io_list_all),
+                io_2_1_stdout: extra.io_2_1_stdout.or(symbols.io_2_1_stdout),
+                debug_file: Some(path),
+            };
+        }
+        symbols
+    }
+
+    fn from_elf(elf: &Elf) -> Self {
+        let symbols = elf.symbols();
+        let find = |name: &str| {
+            symbols
+                .iter()
+                .find(|s| s.name.split('@').next() == Some(name) && (s.value != 0 || s.size != 0))
+                .map(|s| s.value)
+        };
+        Self {
+            main_arena: find("main_arena"),
+            mp: find("mp_"),
+            global_max_fast: find("global_max_fast"),
+            tcache: find("tcache"),
+            thread_arena: find("thread_arena"),
+            io_list_all: find("_IO_list_all"),
+            io_2_1_stdout: find("_IO_2_1_stdout_"),
+            debug_file: None,
+        }
+    }
+}
+
+/// Places gdb would look for the separate debug file of `libc`.
+fn debug_file_candidates(libc: &Elf, libc_path: &str, dir: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    if let Some(id) = libc.build_id().filter(|id| id.len() > 2) {
+        out.push(
+            dir.join(".build-id")
+                .join(&id[..2])
+                .join(format!("{}.debug", &id[2..])),
+        );
+    }
+    if let Some(link) = libc.debuglink() {
+        let libc_dir = Path::new(libc_path).parent().unwrap_or(Path::new("/"));
+        out.push(dir.join(&link));
+        out.push(dir.join(libc_dir.strip_prefix("/").unwrap_or(libc_dir)).join(&link));
+        out.push(libc_dir.join(".debug").join(&link));
+    }
+    out.retain(|p| p.is_file());
+    out
+}
+
+/// Works out the offset of `main_arena` from the base of the libc mapped by
+/// the target. Exact symbols win; otherwise the arena is located in memory.
+pub fn main_arena_offset(
+    proc: &Proc,
+    symbols: &LibcSymbols,
+    libc_base: u64,
+    malloc_state: &CStructDef,
+    size_t: usize,
+) -> Option<u64> {
+    symbols
+        .main_arena
+        .or_else(|| scan_main_arena(proc, malloc_state, size_t).map(|a| a - libc_base))
+}
+
+/// DEFAULT_MMAP_THRESHOLD_MAX: the dynamic threshold never grows past it.
+pub fn mmap_threshold_max(size_t: usize) -> u64 {
+    if size_t == 4 {
+        512 * 1024
+    } else {
+        4 * 1024 * 1024 * size_t as u64
+    }
+}
+
+/// Offset of `mp_`. Without a symbol, looks in libc's data for a
+/// `malloc_par` whose `sbrk_base` is the start of `[heap]`.
+pub fn mp_offset(
+    proc: &Proc,
+    symbols: &LibcSymbols,
+    libc_base: u64,
+    malloc_par: &CStructDef,
+    size_t: usize,
+) -> Option<u64> {
+    if let Some(offset) = symbols.mp {
+        return Some(offset);
+    }
+
+    let heap_start = proc.ranges().get("heap")?.first()?.start;
+    let sbrk_off = malloc_par.offsetof("sbrk_base")?;
+    let field = |mem: &[u8], base: usize, name: &str| {
+        let off = base + malloc_par.offsetof(name)?;
+        mem.get(off..off + size_t).map(uk64)
+    };
+
+    for range in proc.libc_data_ranges() {
+        let size = usize::try_from(range.end - range.start).ok()?;
+        let mem = match proc.read(range.start, size) {
+            Some(m) => m,
+            None => continue,
+        };
+        let mut off = sbrk_off;
+        while off + size_t <= mem.len() {
+            let base = off - sbrk_off;
+            let plausible = uk64(&mem[off..off + size_t]) == heap_start
+                && field(&mem, base, "mmap_threshold").is_some_and(|t| t != 0 && t <= mmap_threshold_max(size_t))
+                && field(&mem, base, "tcache_bins").is_none_or(|n| n <= 128);
+            if plausible {
+                return Some(range.start + base as u64 - libc_base);
+            }
+            off += size_t;
+        }
+    }
+    None
+}
+
+const VERSION_BANNER: &str = "release version ";
+
+/// Reads the glibc version from the "GNU C Library ... release version X.Y."
+/// banner, first in the target's memory and then in the libc file on disk.
+pub fn libc_version(proc: &Proc, libc_path: Option<&str>) -> Option<String> {
+    let in_memory = proc
+        .search_in_libc(VERSION_BANNER)
+        .first()
+        .and_then(|(addr, _)| proc.read(addr + VERSION_BANNER.len() as u64, 16))
+        .and_then(|tail| parse_banner_version(&tail));
+
+    in_memory.or_else(|| {
+        let data = fs::read(libc_path?).ok()?;
+        let needle = VERSION_BANNER.as_bytes();
+        let pos = data.windows(needle.len()).position(|w| w == needle)?;
+        parse_banner_version(&data[pos + needle.len()..])
+    })
+}
+
+fn parse_banner_version(tail: &[u8]) -> Option<String> {
+    let version: String = tail
+        .iter()
+        .take(16)
+        .map(|&b| b as char)
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    let version = version.trim_end_matches('.');
+    version.contains('.').then(|| version.to_string())
+}
+
+/// Searches libc's writable data for a `malloc_state` whose `bins` are all
+/// either empty (pointing back at themselves) or lead into the heap, and
+/// whose `top` lies in `[heap]`.
+fn scan_main_arena(proc: &Proc, malloc_state: &CStructDef, size_t: usize) -> Option<u64> {
+    let ranges = proc.ranges();
+    let heap = ranges.get("heap").cloned().unwrap_or_default();
+    let bins_off = malloc_state.offsetof("bins")?;
+    let top_off = malloc_state.offsetof("top")?;
+    let arena_size = malloc_state.size();
+
+    let mut best: Option<(usize, u64)> = None;
+    for range in proc.libc_data_ranges() {
+        let size = usize::try_from(range.end - range.start).ok()?;
+        let mem = match proc.read(range.start, size) {
+            Some(m) => m,
+            None => continue,
+        };
+
+        let mut off = 0;
+        while off + arena_size <= mem.len() {
+            let addr = range.start + off as u64;
+            let score = arena_score(&mem[off..], addr, bins_off, top_off, size_t, &heap);
+            if let Some(score) = score
+                && best.is_none_or(|(s, _)| score > s)
+            {
+                best = Some((score, addr));
+            }
+            off += size_t;
+        }
+    }
+    best.map(|(_, addr)| addr)
+}
+
+/// Number of empty bins if `mem` looks like a live `malloc_state` at `addr`.
+fn arena_score(
+    mem: &[u8],
+    addr: u64,
+    bins_off: usize,
+    top_off: usize,
+    size_t: usize,
+    heap: &[Range<u64>],
+) -> Option<usize> {
+    let word = |off: usize| uk64(&mem[off..off + size_t]);
+    let in_heap = |ptr: u64| heap.iter().any(|r| r.contains(&ptr));
+
+    let mut empty = 0;
+    for i in 0..NBINS - 1 {
+        let fd_off = bins_off + 2 * i * size_t;
+        let fd = word(fd_off);
+        let bk = word(fd_off + size_t);
+        // bin_at(i) points two size_t before its fd slot.
+        let head = addr + fd_off as u64 - 2 * size_t as u64;
+        if fd == head && bk == head {
+            empty += 1;
+        } else if !(in_heap(fd) && in_heap(bk)) {
+            return None;
+        }
+    }
+
+    // An arena that never allocated keeps top at the unsorted bin head.
+    let top = word(top_off);
+    let initial_top = addr + bins_off as u64 - 2 * size_t as u64;
+    if empty < NBINS / 2 || !(in_heap(top) || top == initial_top) {
+        return None;
+    }
+    Some(empty)
+}
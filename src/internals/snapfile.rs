@@ -0,0 +1,311 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::common::{parse_u64, uk64, Arch};
+use super::core::{Snapshot, TargetInfo};
+use super::image::{MemoryImage, Region, ThreadPointer};
+use super::proc::{Map, Maps};
+use super::profile::{json_field, json_string, json_unquote};
+use super::resolve::LibcSymbols;
+
+const MAGIC: &str = "heapfinder snapshot 1\n";
+const DATA_MARKER: &str = "data\n";
+const PAGE_SIZE: usize = 0x1000;
+
+/// Writes `snap` to `path`. The file starts with a text header (libc info
+/// as flat JSON, then `map`, `thread` and JSON-quoted `warning` lines)
+/// followed by the captured regions; all-zero pages are stored as a single
+/// tag byte.
+pub fn save(snap: &Snapshot, path: &Path) -> io::Result<()> {
+    let info = snap.info();
+    let image = snap.image();
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC.as_bytes());
+    out.extend_from_slice(info_json(info).as_bytes());
+    for m in image.maps().iter() {
+        let line = format!("map {:x}-{:x} {} {}\n", m.range().start, m.range().end, m.perm(), m.mapname());
+        out.extend_from_slice(line.as_bytes());
+    }
+    for t in image.threads() {
+        out.extend_from_slice(format!("thread {} 0x{:x}\n", t.tid, t.thread_pointer).as_bytes());
+    }
+    for w in &info.warnings {
+        out.extend_from_slice(format!("warning {}\n", json_string(w)).as_bytes());
+    }
+    out.extend_from_slice(DATA_MARKER.as_bytes());
+
+    for region in image.regions() {
+        out.extend_from_slice(&region.addr.to_le_bytes());
+        out.extend_from_slice(&(region.data.len() as u64).to_le_bytes());
+        for page in region.data.chunks(PAGE_SIZE) {
+            if page.iter().all(|&b| b == 0) {
+                out.push(0);
+            } else {
+                out.push(1);
+                out.extend_from_slice(page);
+            }
+        }
+    }
+    fs::File::create(path)?.write_all(&out)
+}
+
+/// Reads a snapshot written by `save`.
+pub fn load(path: &Path) -> io::Result<Snapshot> {
+    let bad = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), what),
+        )
+    };
+    let data = fs::read(path)?;
+    if !data.starts_with(MAGIC.as_bytes()) {
+        return Err(bad("not a heapfinder snapshot"));
+    }
+    let header_end = find(&data, format!("\n{}", DATA_MARKER).as_bytes())
+        .ok_or_else(|| bad("missing data section"))?
+        + 1;
+    let header = String::from_utf8_lossy(&data[MAGIC.len()..header_end]);
+    let json_end = header.find("\n}\n").ok_or_else(|| bad("bad header"))? + 3;
+    let mut info = parse_info(&header[..json_end]).ok_or_else(|| bad("bad header"))?;
+
+    let mut maps = Vec::new();
+    let mut threads = Vec::new();
+    for line in header[json_end..].lines() {
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match kind {
+            "map" => {
+                let mut parts = rest.splitn(3, ' ');
+                let (range, perm, name) = (parts.next(), parts.next(), parts.next());
+                let (start, end) = range
+                    .and_then(|r| r.split_once('-'))
+                    .ok_or_else(|| bad("bad map line"))?;
+                let (start, end) = u64::from_str_radix(start, 16)
+                    .ok()
+                    .zip(u64::from_str_radix(end, 16).ok())
+                    .ok_or_else(|| bad("bad map line"))?;
+                maps.push(Map::new(start..end, perm.unwrap_or(""), name.unwrap_or("mapped")));
+            }
+            "thread" => {
+                let (tid, tp) = rest.split_once(' ').ok_or_else(|| bad("bad thread line"))?;
+                threads.push(ThreadPointer {
+                    tid: parse_u64(tid).ok_or_else(|| bad("bad thread line"))?,
+                    thread_pointer: parse_u64(tp).ok_or_else(|| bad("bad thread line"))?,
+                });
+            }
+            "warning" => info.warnings.push(json_unquote(rest).ok_or_else(|| bad("bad warning line"))?),
+            _ => {}
+        }
+    }
+
+    let mut regions = Vec::new();
+    let mut pos = header_end + DATA_MARKER.len();
+    while pos < data.len() {
+        let word = |at: usize| data.get(at..at + 8).map(uk64).ok_or_else(|| bad("truncated"));
+        let addr = word(pos)?;
+        let len = usize::try_from(word(pos + 8)?).map_err(|_| bad("bad region"))?;
+        pos += 16;
+        // Every page takes at least its tag byte.
+        if len.div_ceil(PAGE_SIZE) > data.len() - pos {
+            return Err(bad("bad region"));
+        }
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let page = PAGE_SIZE.min(len - bytes.len());
+            match data.get(pos) {
+                Some(0) => {
+                    bytes.resize(bytes.len() + page, 0);
+                    pos += 1;
+                }
+                Some(1) => {
+                    let src = data.get(pos + 1..pos + 1 + page).ok_or_else(|| bad("truncated"))?;
+                    bytes.extend_from_slice(src);
+                    pos += 1 + page;
+                }
+                _ => return Err(bad("bad page tag")),
+            }
+        }
+        regions.push(Region { addr, data: bytes });
+    }
+
+    let image = MemoryImage::new(Maps::new(maps), regions, threads);
+    Ok(Snapshot::new(info, image))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn info_json(info: &TargetInfo) -> String {
+    let mut fields = vec![
+        format!("\"pid\": {}", info.pid),
+        format!("\"arch\": \"{:?}\"", info.arch),
+        format!("\"libc_version\": {}", json_string(&info.libc_version)),
+        format!("\"tcache_enable\": {}", info.tcache_enable),
+        format!("\"main_arena_offset\": \"0x{:x}\"", info.main_arena_offset),
+        format!("\"tls_size\": \"0x{:x}\"", info.tls_size),
+        format!("\"exe_path\": {}", json_string(&info.exe_path.display().to_string())),
+    ];
+    let paths = [
+        ("libc_path", info.libc_path.clone()),
+        ("ld_path", info.ld_path.clone()),
+        (
+            "debug_file",
+            info.symbols.debug_file.as_ref().map(|p| p.display().to_string()),
+        ),
+    ];
+    for (name, value) in paths {
+        if let Some(v) = value {
+            fields.push(format!("\"{}\": {}", name, json_string(&v)));
+        }
+    }
+    let sym = &info.symbols;
+    let offsets = [
+        ("mp_offset", info.mp_offset),
+        ("sym_main_arena", sym.main_arena),
+        ("sym_mp", sym.mp),
+        ("sym_global_max_fast", sym.global_max_fast),
+        ("sym_tcache", sym.tcache),
+        ("sym_thread_arena", sym.thread_arena),
+        ("sym_io_list_all", sym.io_list_all),
+        ("sym_io_2_1_stdout", sym.io_2_1_stdout),
+    ];
+    for (name, value) in offsets {
+        if let Some(v) = value {
+            fields.push(format!("\"{}\": \"0x{:x}\"", name, v));
+        }
+    }
+    if let Some(n) = info.tcache_bins {
+        fields.push(format!("\"tcache_bins\": {}", n));
+    }
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
+
+fn parse_info(text: &str) -> Option<TargetInfo> {
+    let offset = |key| json_field(text, key).as_deref().and_then(parse_u64);
+    let string = |key| json_field(text, key);
+    Some(TargetInfo {
+        pid: offset("pid")?,
+        arch: match json_field(text, "arch")?.as_str() {
+            "X86" => Arch::X86,
+            "X64" => Arch::X64,
+            _ => return None,
+        },
+        libc_version: string("libc_version")?,
+        tcache_enable: json_field(text, "tcache_enable")? == "true",
+        main_arena_offset: offset("main_arena_offset")?,
+        mp_offset: offset("mp_offset"),
+        tcache_bins: offset("tcache_bins"),
+        libc_path: string("libc_path"),
+        ld_path: string("ld_path"),
+        exe_path: PathBuf::from(json_field(text, "exe_path")?),
+        symbols: LibcSymbols {
+            main_arena: offset("sym_main_arena"),
+            mp: offset("sym_mp"),
+            global_max_fast: offset("sym_global_max_fast"),
+            tcache: offset("sym_tcache"),
+            thread_arena: offset("sym_thread_arena"),
+            io_list_all: offset("sym_io_list_all"),
+            io_2_1_stdout: offset("sym_io_2_1_stdout"),
+            debug_file: json_field(text, "debug_file").map(PathBuf::from),
+        },
+        tls_size: offset("tls_size")?,
+        warnings: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("heapfinder-{}-{}.snap", name, std::process::id()))
+    }
+
+    fn sample() -> Snapshot {
+        let info = TargetInfo {
+            pid: 1234,
+            main_arena_offset: 0x21ac80,
+            mp_offset: Some(0x21a360),
+            tcache_bins: Some(64),
+            libc_path: Some("/lib/x86_64-linux-gnu/libc.so.6".to_string()),
+            exe_path: PathBuf::from("/tmp/a \"quoted\" \\ name"),
+            symbols: LibcSymbols {
+                global_max_fast: Some(0x221500),
+                ..LibcSymbols::default()
+            },
+            warnings: vec!["first \"line\"\nsecond \\ line".to_string()],
+            ..TargetInfo::sample(Arch::X64)
+        };
+        let maps = vec![
+            Map::new(0x5000..0x8000, "rw-p", "[heap]"),
+            Map::new(0x7f0000000000..0x7f0000001000, "rw-p", "mapped"),
+        ];
+        let mut heap = vec![0u8; 3 * PAGE_SIZE];
+        heap[PAGE_SIZE + 8] = 0x21;
+        heap[3 * PAGE_SIZE - 1] = 0xff;
+        let regions = vec![
+            Region { addr: 0x5000, data: heap },
+            Region { addr: 0x7f0000000000, data: vec![0xaa; 0x10] },
+        ];
+        let threads = vec![ThreadPointer { tid: 1235, thread_pointer: 0x7f0000000740 }];
+        Snapshot::new(info, MemoryImage::new(Maps::new(maps), regions, threads))
+    }
+
+    #[test]
+    fn round_trip() {
+        let snap = sample();
+        let path = temp_path("round-trip");
+        save(&snap, &path).unwrap();
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        let (a, b) = (snap.info(), loaded.info());
+        assert_eq!(a.pid, b.pid);
+        assert_eq!(a.libc_version, b.libc_version);
+        assert_eq!(a.tcache_enable, b.tcache_enable);
+        assert_eq!(a.main_arena_offset, b.main_arena_offset);
+        assert_eq!(a.mp_offset, b.mp_offset);
+        assert_eq!(a.tcache_bins, b.tcache_bins);
+        assert_eq!(a.libc_path, b.libc_path);
+        assert_eq!(a.ld_path, b.ld_path);
+        assert_eq!(a.exe_path, b.exe_path);
+        assert_eq!(a.symbols.global_max_fast, b.symbols.global_max_fast);
+        assert_eq!(a.symbols.main_arena, b.symbols.main_arena);
+        assert_eq!(a.tls_size, b.tls_size);
+        assert_eq!(a.warnings, b.warnings);
+
+        let (a, b) = (snap.image(), loaded.image());
+        let maps = |image: &MemoryImage| {
+            image
+                .maps()
+                .iter()
+                .map(|m| (m.range().clone(), m.perm().to_string(), m.mapname().to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(maps(a), maps(b));
+        assert_eq!(a.regions().len(), b.regions().len());
+        for (x, y) in a.regions().iter().zip(b.regions()) {
+            assert_eq!(x.addr, y.addr);
+            assert_eq!(x.data, y.data);
+        }
+        assert_eq!(b.threads().len(), 1);
+        assert_eq!(b.threads()[0].tid, 1235);
+        assert_eq!(b.threads()[0].thread_pointer, 0x7f0000000740);
+    }
+
+    #[test]
+    fn rejects_oversized_region() {
+        let path = temp_path("oversized");
+        save(&sample(), &path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        let start = find(&data, format!("\n{}", DATA_MARKER).as_bytes()).unwrap() + 1 + DATA_MARKER.len();
+        data[start + 8..start + 16].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        fs::write(&path, &data).unwrap();
+        let err = load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with("bad region"));
+    }
+}
@@ -1,5 +1,11 @@
+use super::common::{parse_version, uk64, Arch};
+
+/// Layout used when the glibc version could not be detected.
+const DEFAULT_VERSION: (u32, u32) = (2, 41);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FieldType {
+    Int8,
     Int16,
     Int,
     Ptr,
@@ -7,11 +13,12 @@ enum FieldType {
 }
 
 impl FieldType {
-    fn size(self) -> usize {
+    fn size(self, ptr_size: usize) -> usize {
         match self {
+            FieldType::Int8 => 1,
             FieldType::Int16 => 2,
             FieldType::Int => 4,
-            FieldType::Ptr | FieldType::SizeT => 8,
+            FieldType::Ptr | FieldType::SizeT => ptr_size,
         }
     }
 }
@@ -26,24 +33,25 @@ struct FieldDef {
 #[derive(Debug, Clone)]
 pub struct CStructDef {
     fields: Vec<FieldDef>,
+    ptr_size: usize,
 }
 
 impl CStructDef {
-    fn new(fields: Vec<FieldDef>) -> Self {
-        Self { fields }
+    fn new(fields: Vec<FieldDef>, ptr_size: usize) -> Self {
+        Self { fields, ptr_size }
     }
 
     pub fn size(&self) -> usize {
         self.fields
             .iter()
-            .map(|f| f.typ.size() * f.count)
+            .map(|f| f.typ.size(self.ptr_size) * f.count)
             .sum()
     }
 
     fn field_def_offset(&self, name: &str) -> Option<(usize, &FieldDef)> {
         let mut offset = 0usize;
         for f in &self.fields {
-            let size = f.typ.size();
+            let size = f.typ.size(self.ptr_size);
             if f.name == name {
                 return Some((offset, f));
             }
@@ -57,7 +65,18 @@ impl CStructDef {
         if index >= f.count {
             return None;
         }
-        Some(offset + index * f.typ.size())
+        Some(offset + index * f.typ.size(self.ptr_size))
+    }
+
+    /// Number of elements of the array field `name`.
+    pub fn count_of(&self, name: &str) -> Option<usize> {
+        self.field_def_offset(name).map(|(_, f)| f.count)
+    }
+
+    /// Offset of `name` or `name[index]` from the start of the struct.
+    pub fn offsetof(&self, var: &str) -> Option<usize> {
+        let (name, idx) = parse_name_index(var)?;
+        self.offset_of(name, idx.unwrap_or(0))
     }
 
     pub fn new_instance(&self, mut memdump: Vec<u8>, addr: u64) -> CStructInstance {
@@ -81,10 +100,15 @@ pub struct CStructInstance {
 }
 
 impl CStructInstance {
+    #[allow(dead_code)]
     pub fn size(&self) -> usize {
         self.def.size()
     }
 
+    pub fn ptr_size(&self) -> usize {
+        self.def.ptr_size
+    }
+
     pub fn addr(&self) -> u64 {
         self.addr
     }
@@ -106,9 +130,9 @@ impl CStructInstance {
         if index >= f.count {
             return None;
         }
-        let off = offset + index * f.typ.size();
-        let slice = self.mem.get(off..off + 8)?;
-        Some(read_u64_le(slice))
+        let off = offset + index * f.typ.size(self.def.ptr_size);
+        let slice = self.mem.get(off..off + f.typ.size(self.def.ptr_size))?;
+        Some(uk64(slice))
     }
 
     pub fn get_ptr_array(&self, name: &str) -> Option<Vec<u64>> {
@@ -116,12 +140,12 @@ impl CStructInstance {
         if !matches!(f.typ, FieldType::Ptr | FieldType::SizeT) {
             return None;
         }
-        let size = f.typ.size();
+        let size = f.typ.size(self.def.ptr_size);
         let mut out = Vec::with_capacity(f.count);
         for i in 0..f.count {
             let off = offset + i * size;
             let slice = self.mem.get(off..off + size)?;
-            out.push(read_u64_le(slice));
+            out.push(uk64(slice));
         }
         Some(out)
     }
@@ -136,11 +160,24 @@ impl CStructInstance {
         if index >= f.count {
             return None;
         }
-        let off = offset + index * f.typ.size();
+        let off = offset + index * f.typ.size(self.def.ptr_size);
         let slice = self.mem.get(off..off + 4)?;
         Some(read_u32_le(slice))
     }
 
+    pub fn get_u8(&self, var: &str) -> Option<u8> {
+        let (name, idx) = parse_name_index(var)?;
+        let index = idx.unwrap_or(0);
+        let (offset, f) = self.def.field_def_offset(name)?;
+        if f.typ != FieldType::Int8 {
+            return None;
+        }
+        if index >= f.count {
+            return None;
+        }
+        self.mem.get(offset + index).copied()
+    }
+
     pub fn get_u16(&self, var: &str) -> Option<u16> {
         let (name, idx) = parse_name_index(var)?;
         let index = idx.unwrap_or(0);
@@ -151,7 +188,7 @@ impl CStructInstance {
         if index >= f.count {
             return None;
         }
-        let off = offset + index * f.typ.size();
+        let off = offset + index * f.typ.size(self.def.ptr_size);
         let slice = self.mem.get(off..off + 2)?;
         Some(read_u16_le(slice))
     }
@@ -174,12 +211,6 @@ fn parse_name_index(s: &str) -> Option<(&str, Option<usize>)> {
     }
 }
 
-fn read_u64_le(slice: &[u8]) -> u64 {
-    let mut buf = [0u8; 8];
-    buf.copy_from_slice(slice);
-    u64::from_le_bytes(buf)
-}
-
 fn read_u32_le(slice: &[u8]) -> u32 {
     let mut buf = [0u8; 4];
     buf.copy_from_slice(slice);
@@ -192,29 +223,58 @@ fn read_u16_le(slice: &[u8]) -> u16 {
     u16::from_le_bytes(buf)
 }
 
-fn malloc_state_struct_64() -> CStructDef {
+/// MALLOC_ALIGNMENT: i386 moved to 16 in 2.26, everything else uses
+/// two `size_t`.
+fn alignment(version: (u32, u32), ptr_size: usize) -> usize {
+    if ptr_size == 4 && version >= (2, 26) {
+        16
+    } else {
+        2 * ptr_size
+    }
+}
+
+/// NFASTBINS: `fastbin_index(request2size(MAX_FAST_SIZE)) + 1`.
+fn nfastbins(version: (u32, u32), ptr_size: usize) -> usize {
+    let align_mask = alignment(version, ptr_size) - 1;
+    let max_fast = 80 * ptr_size / 4;
+    let size = (max_fast + ptr_size + align_mask) & !align_mask;
+    let shift = if ptr_size == 8 { 4 } else { 3 };
+    (size >> shift) - 2 + 1
+}
+
+fn malloc_state_struct(version: (u32, u32), ptr_size: usize) -> CStructDef {
     use FieldType::*;
-    let fields = vec![
+    let mut fields = vec![
         FieldDef { typ: Int, name: "mutex", count: 1 },
         FieldDef { typ: Int, name: "flags", count: 1 },
-        FieldDef { typ: Int, name: "have_fastchunks", count: 1 },
-        FieldDef { typ: Int, name: "align", count: 1 },
-        FieldDef { typ: Ptr, name: "fastbinsY", count: 10 },
+    ];
+    if version >= (2, 27) {
+        fields.push(FieldDef { typ: Int, name: "have_fastchunks", count: 1 });
+        if ptr_size == 8 {
+            fields.push(FieldDef { typ: Int, name: "align", count: 1 });
+        }
+    }
+    fields.extend([
+        FieldDef { typ: Ptr, name: "fastbinsY", count: nfastbins(version, ptr_size) },
         FieldDef { typ: Ptr, name: "top", count: 1 },
         FieldDef { typ: Ptr, name: "last_remainder", count: 1 },
         FieldDef { typ: Ptr, name: "bins", count: 254 },
         FieldDef { typ: Int, name: "binmap", count: 4 },
         FieldDef { typ: Ptr, name: "next", count: 1 },
         FieldDef { typ: Ptr, name: "next_free", count: 1 },
-        FieldDef { typ: SizeT, name: "attached_threads", count: 1 },
+    ]);
+    if version >= (2, 23) {
+        fields.push(FieldDef { typ: SizeT, name: "attached_threads", count: 1 });
+    }
+    fields.extend([
         FieldDef { typ: SizeT, name: "system_mem", count: 1 },
         FieldDef { typ: SizeT, name: "max_system_mem", count: 1 },
-    ];
-    CStructDef::new(fields)
+    ]);
+    CStructDef::new(fields, ptr_size)
 }
 
 
-fn malloc_chunk_struct_64() -> CStructDef {
+fn malloc_chunk_struct(ptr_size: usize) -> CStructDef {
     use FieldType::*;
     let fields = vec![
         FieldDef { typ: SizeT, name: "prev_size", count: 1 },
@@ -224,26 +284,119 @@ fn malloc_chunk_struct_64() -> CStructDef {
         FieldDef { typ: Ptr, name: "fd_nextsize", count: 1 },
         FieldDef { typ: Ptr, name: "bk_nextsize", count: 1 },
     ];
-    CStructDef::new(fields)
+    CStructDef::new(fields, ptr_size)
 }
 
-fn tcache_perthread_struct_64() -> CStructDef {
+fn tcache_perthread_struct(version: (u32, u32), ptr_size: usize) -> CStructDef {
     use FieldType::*;
+    // 2.42 added TCACHE_LARGE_BINS on top of the 64 small bins.
+    let bins = if version >= (2, 42) { 76 } else { 64 };
+    let counts = if version >= (2, 30) { Int16 } else { Int8 };
     let fields = vec![
-        FieldDef { typ: Int16, name: "counts", count: 64 },
-        FieldDef { typ: Ptr, name: "entries", count: 64 },
+        FieldDef { typ: counts, name: "counts", count: bins },
+        FieldDef { typ: Ptr, name: "entries", count: bins },
+    ];
+    CStructDef::new(fields, ptr_size)
+}
+
+fn malloc_par_struct(version: (u32, u32), ptr_size: usize) -> CStructDef {
+    use FieldType::*;
+    let mut fields = vec![
+        FieldDef { typ: SizeT, name: "trim_threshold", count: 1 },
+        FieldDef { typ: SizeT, name: "top_pad", count: 1 },
+        FieldDef { typ: SizeT, name: "mmap_threshold", count: 1 },
+        FieldDef { typ: SizeT, name: "arena_test", count: 1 },
+        FieldDef { typ: SizeT, name: "arena_max", count: 1 },
+    ];
+    if version >= (2, 35) {
+        fields.extend([
+            FieldDef { typ: SizeT, name: "thp_pagesize", count: 1 },
+            FieldDef { typ: SizeT, name: "hp_pagesize", count: 1 },
+            FieldDef { typ: Int, name: "hp_flags", count: 1 },
+        ]);
+    }
+    fields.extend([
+        FieldDef { typ: Int, name: "n_mmaps", count: 1 },
+        FieldDef { typ: Int, name: "n_mmaps_max", count: 1 },
+        FieldDef { typ: Int, name: "max_n_mmaps", count: 1 },
+        FieldDef { typ: Int, name: "no_dyn_threshold", count: 1 },
+    ]);
+    if version >= (2, 35) && ptr_size == 8 {
+        fields.push(FieldDef { typ: Int, name: "align", count: 1 });
+    }
+    fields.extend([
+        FieldDef { typ: SizeT, name: "mmapped_mem", count: 1 },
+        FieldDef { typ: SizeT, name: "max_mmapped_mem", count: 1 },
+        FieldDef { typ: Ptr, name: "sbrk_base", count: 1 },
+    ]);
+    if version >= (2, 26) {
+        fields.extend([
+            FieldDef { typ: SizeT, name: "tcache_bins", count: 1 },
+            FieldDef { typ: SizeT, name: "tcache_max_bytes", count: 1 },
+            FieldDef { typ: SizeT, name: "tcache_count", count: 1 },
+            FieldDef { typ: SizeT, name: "tcache_unsorted_limit", count: 1 },
+        ]);
+    }
+    CStructDef::new(fields, ptr_size)
+}
+
+fn heap_info_struct(version: (u32, u32), ptr_size: usize) -> CStructDef {
+    use FieldType::*;
+    let mut fields = vec![
+        FieldDef { typ: Ptr, name: "ar_ptr", count: 1 },
+        FieldDef { typ: Ptr, name: "prev", count: 1 },
+        FieldDef { typ: SizeT, name: "size", count: 1 },
+        FieldDef { typ: SizeT, name: "mprotect_size", count: 1 },
     ];
-    CStructDef::new(fields)
+    if version >= (2, 35) {
+        fields.push(FieldDef { typ: SizeT, name: "pagesize", count: 1 });
+    }
+    // The pad makes sizeof (heap_info) + 2 * SIZE_SZ a multiple of
+    // MALLOC_ALIGNMENT, so the first chunk's user pointer is aligned.
+    let used = (fields.len() + 2) * ptr_size;
+    let pad = used.wrapping_neg() & (alignment(version, ptr_size) - 1);
+    if pad != 0 {
+        fields.push(FieldDef { typ: Int8, name: "pad", count: pad });
+    }
+    CStructDef::new(fields, ptr_size)
+}
+
+fn layout_version(version: &str) -> (u32, u32) {
+    parse_version(version).unwrap_or(DEFAULT_VERSION)
+}
+
+fn ptr_size(arch: Arch) -> usize {
+    match arch {
+        Arch::X86 => 4,
+        Arch::X64 => 8,
+    }
 }
 
-pub fn malloc_state_generator(_version: &str) -> CStructDef {
-    malloc_state_struct_64()
+pub fn malloc_alignment(version: &str, arch: Arch) -> usize {
+    alignment(layout_version(version), ptr_size(arch))
 }
 
-pub fn malloc_chunk_generator(_version: &str) -> CStructDef {
-    malloc_chunk_struct_64()
+pub fn malloc_state_generator(version: &str, arch: Arch) -> CStructDef {
+    malloc_state_struct(layout_version(version), ptr_size(arch))
+}
+
+pub fn malloc_par_generator(version: &str, arch: Arch) -> CStructDef {
+    malloc_par_struct(layout_version(version), ptr_size(arch))
+}
+
+pub fn malloc_chunk_generator(_version: &str, arch: Arch) -> CStructDef {
+    malloc_chunk_struct(ptr_size(arch))
+}
+
+/// `None` for glibc older than 2.26, which has no tcache.
+pub fn tcache_struct_generator(version: &str, arch: Arch) -> Option<CStructDef> {
+    let version = layout_version(version);
+    if version < (2, 26) {
+        return None;
+    }
+    Some(tcache_perthread_struct(version, ptr_size(arch)))
 }
 
-pub fn tcache_struct_generator(_version: &str) -> CStructDef {
-    tcache_perthread_struct_64()
+pub fn heap_info_generator(version: &str, arch: Arch) -> CStructDef {
+    heap_info_struct(layout_version(version), ptr_size(arch))
 }
@@ -0,0 +1,565 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use libc::{pid_t, user_regs_struct};
+
+use super::common::Arch;
+use super::elf::Elf;
+use super::proc::{Maps, Proc};
+use super::ptrace;
+
+const INT3: u8 = 0xcc;
+
+/// A traced allocator entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    Malloc,
+    Free,
+    Calloc,
+    Realloc,
+    Memalign,
+}
+
+impl CallKind {
+    pub const ALL: [CallKind; 5] = [
+        Self::Malloc,
+        Self::Free,
+        Self::Calloc,
+        Self::Realloc,
+        Self::Memalign,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Malloc => "malloc",
+            Self::Free => "free",
+            Self::Calloc => "calloc",
+            Self::Realloc => "realloc",
+            Self::Memalign => "memalign",
+        }
+    }
+
+    fn argc(self) -> usize {
+        match self {
+            Self::Malloc | Self::Free => 1,
+            Self::Calloc | Self::Realloc | Self::Memalign => 2,
+        }
+    }
+
+    /// Whether the call returns a pointer worth recording.
+    fn returns(self) -> bool {
+        self != Self::Free
+    }
+}
+
+impl fmt::Display for CallKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One completed allocator call.
+#[derive(Debug, Clone)]
+pub struct HeapCall {
+    /// When the call returned, since tracing started.
+    pub time: Duration,
+    pub tid: u64,
+    pub kind: CallKind,
+    pub args: Vec<u64>,
+    /// The returned pointer; `None` for `free`.
+    pub ret: Option<u64>,
+    /// Return address of the call.
+    pub caller: u64,
+    /// The file mapped at `caller` and the offset into it.
+    pub caller_site: Option<(String, u64)>,
+}
+
+impl HeapCall {
+    /// The user pointer the call released (`free`, `realloc`).
+    pub fn freed(&self) -> Option<u64> {
+        match self.kind {
+            CallKind::Free | CallKind::Realloc => self.args.first().copied().filter(|&p| p != 0),
+            _ => None,
+        }
+    }
+
+    /// The user pointer the call handed out.
+    pub fn allocated(&self) -> Option<u64> {
+        self.ret.filter(|&p| p != 0)
+    }
+}
+
+/// Set by the signal handler to end `Tracer::run`.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+const STOP_SIGNALS: [i32; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+extern "C" fn request_stop(_: i32) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug)]
+struct Breakpoint {
+    orig: u8,
+    /// Set on allocator entry points.
+    entry: Option<CallKind>,
+    /// Outstanding calls that return here.
+    returns: usize,
+}
+
+/// A call that has been entered but has not returned yet.
+#[derive(Debug)]
+struct Pending {
+    kind: CallKind,
+    args: Vec<u64>,
+    caller: u64,
+    /// Stack pointer at entry, where `caller` is stored.
+    sp: u64,
+    /// Made by another traced call, e.g. `realloc(NULL, n)` calling
+    /// `malloc`; not recorded on its own.
+    nested: bool,
+}
+
+/// Software breakpoints on libc's allocator entry points and on the return
+/// address of every call in flight. A breakpoint is stepped over by
+/// restoring its byte for one instruction while the other threads are held.
+pub struct Tracer {
+    pid: pid_t,
+    arch: Arch,
+    maps: Maps,
+    breakpoints: HashMap<u64, Breakpoint>,
+    /// Return sites whose breakpoint has been taken out again.
+    removed: HashSet<u64>,
+    /// Traced threads and their calls in flight, innermost last.
+    threads: HashMap<pid_t, Vec<Pending>>,
+    /// Threads in group-stop, waiting for SIGCONT.
+    group_stopped: HashSet<pid_t>,
+    /// Stops reaped while holding threads, handled before waiting again.
+    queued: VecDeque<(pid_t, i32)>,
+    start: Instant,
+}
+
+impl Tracer {
+    /// Resolves the entry points in the libc mapped by `pid`, attaches to
+    /// every thread and plants the breakpoints. Threads created later are
+    /// traced too.
+    pub fn attach(pid: u64) -> io::Result<Self> {
+        let proc = Proc::new(pid);
+        let libc_path = proc
+            .libc_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No libc mapped"))?;
+        let libc_base = proc
+            .loaded_base(&libc_path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "libc is not loaded yet"))?;
+        let elf = Elf::open(&libc_path)?;
+        let mut entries = Vec::new();
+        for kind in CallKind::ALL {
+            let sym = elf.symbol(kind.name()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found in {}", kind, libc_path),
+                )
+            })?;
+            entries.push((libc_base + sym.value, kind));
+        }
+
+        let mut tracer = Self {
+            pid: pid as pid_t,
+            arch: proc.arch(),
+            maps: proc.maps(),
+            breakpoints: HashMap::new(),
+            removed: HashSet::new(),
+            threads: HashMap::new(),
+            group_stopped: HashSet::new(),
+            queued: VecDeque::new(),
+            start: Instant::now(),
+        };
+        let signals = tracer.seize_all()?;
+        let Some(&(stopped, _)) = signals.first() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("process {} has no threads", pid),
+            ));
+        };
+        let planted = entries
+            .into_iter()
+            .try_for_each(|(addr, kind)| tracer.insert(stopped, addr, Some(kind)));
+        if let Err(e) = planted {
+            tracer.detach_all(&signals);
+            return Err(e);
+        }
+        for (tid, sig) in signals {
+            let _ = ptrace::cont(tid, sig);
+        }
+        Ok(tracer)
+    }
+
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// Traces until the process exits or heapfinder gets SIGINT, SIGTERM
+    /// or SIGHUP, calling `on_call` for every completed call. The
+    /// breakpoints are removed and the threads released before returning.
+    pub fn run<F: FnMut(&HeapCall)>(mut self, mut on_call: F) -> io::Result<()> {
+        STOP.store(false, Ordering::SeqCst);
+        set_handlers(request_stop as *const () as libc::sighandler_t);
+        let result = self.event_loop(&mut on_call);
+        if !self.threads.is_empty() {
+            self.shutdown();
+        }
+        set_handlers(libc::SIG_DFL);
+        result
+    }
+
+    fn event_loop(&mut self, on_call: &mut dyn FnMut(&HeapCall)) -> io::Result<()> {
+        while !self.threads.is_empty() && !STOP.load(Ordering::SeqCst) {
+            let (tid, status) = match self.queued.pop_front() {
+                Some(stop) => stop,
+                None => match ptrace::wait_raw(-1) {
+                    Ok(v) => v,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                },
+            };
+            self.group_stopped.remove(&tid);
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                self.threads.remove(&tid);
+                continue;
+            }
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+            // Threads created after attaching report in on their own.
+            self.threads.entry(tid).or_default();
+            let sig = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            let result = match (sig, event) {
+                (libc::SIGTRAP, 0) => self.on_trap(tid, on_call),
+                (libc::SIGTRAP, _) => ptrace::cont(tid, 0),
+                // Group-stop: stay stopped until SIGCONT.
+                (_, libc::PTRACE_EVENT_STOP) => {
+                    self.group_stopped.insert(tid);
+                    ptrace::listen(tid)
+                }
+                (sig, _) => ptrace::cont(tid, sig),
+            };
+            // The thread may be gone by now; its exit is reported next.
+            if let Err(e) = result
+                && e.raw_os_error() != Some(libc::ESRCH)
+            {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_trap(&mut self, tid: pid_t, on_call: &mut dyn FnMut(&HeapCall)) -> io::Result<()> {
+        let mut regs = ptrace::getregs(tid)?;
+        let addr = regs.rip.wrapping_sub(1);
+        let Some(bp) = self.breakpoints.get(&addr) else {
+            if self.removed.contains(&addr) {
+                // Hit before another thread's return took it out.
+                regs.rip = addr;
+                ptrace::setregs(tid, &regs)?;
+                return ptrace::cont(tid, 0);
+            }
+            // Not ours: pass the SIGTRAP on.
+            return ptrace::cont(tid, libc::SIGTRAP);
+        };
+        let entry = bp.entry;
+        regs.rip = addr;
+
+        // Every call in flight that returns through this frame, more than
+        // one when an allocator tail-calls another.
+        let frame = regs.rsp.wrapping_sub(self.ptr_size());
+        let mut returning = false;
+        while let Some(pending) = self.threads[&tid].last()
+            && pending.caller == addr
+            && pending.sp == frame
+        {
+            let pending = self.threads.get_mut(&tid).and_then(Vec::pop).unwrap();
+            returning = true;
+            if !pending.nested {
+                let call = HeapCall {
+                    time: self.start.elapsed(),
+                    tid: tid as u64,
+                    kind: pending.kind,
+                    ret: pending.kind.returns().then(|| self.word(regs.rax)),
+                    caller_site: self.locate(pending.caller),
+                    args: pending.args,
+                    caller: pending.caller,
+                };
+                on_call(&call);
+            }
+            self.release_return(tid, addr)?;
+        }
+        if let Some(kind) = entry
+            && !returning
+        {
+            let args = self.args(tid, &regs, kind.argc())?;
+            let caller = self.read_word(tid, regs.rsp)?;
+            let nested = !self.threads[&tid].is_empty();
+            self.threads.get_mut(&tid).unwrap().push(Pending {
+                kind,
+                args,
+                caller,
+                sp: regs.rsp,
+                nested,
+            });
+            self.insert(tid, caller, None)?;
+        }
+        ptrace::setregs(tid, &regs)?;
+        self.step_over(tid, addr)
+    }
+
+    /// Runs the original instruction at `addr` and re-arms the breakpoint
+    /// if it is still wanted.
+    fn step_over(&mut self, tid: pid_t, addr: u64) -> io::Result<()> {
+        let Some(orig) = self.breakpoints.get(&addr).map(|bp| bp.orig) else {
+            return ptrace::cont(tid, 0);
+        };
+        let held = self.hold_others(tid);
+        let result = self.single_step(tid, addr, orig);
+        for t in held {
+            let _ = ptrace::cont(t, 0);
+        }
+        result
+    }
+
+    fn single_step(&mut self, tid: pid_t, addr: u64, orig: u8) -> io::Result<()> {
+        ptrace::poke_byte(tid, addr, orig)?;
+        let mut deferred = 0;
+        loop {
+            ptrace::singlestep(tid, 0)?;
+            let status = wait_thread(tid)?;
+            if !libc::WIFSTOPPED(status) {
+                self.threads.remove(&tid);
+                return Ok(());
+            }
+            match (libc::WSTOPSIG(status), status >> 16) {
+                // An interrupt left over from `hold_others`; the step is
+                // still to come.
+                (_, libc::PTRACE_EVENT_STOP) => {}
+                (libc::SIGTRAP, _) => break,
+                // Delivered once the step is done.
+                (sig, _) => deferred = sig,
+            }
+        }
+        ptrace::poke_byte(tid, addr, INT3)?;
+        ptrace::cont(tid, deferred)
+    }
+
+    /// Stops every running thread but `tid`, so none of them can pass a
+    /// breakpoint while its byte is restored. Threads that stop for some
+    /// other reason first are queued; the rest are returned for resuming.
+    fn hold_others(&mut self, tid: pid_t) -> Vec<pid_t> {
+        let others: Vec<pid_t> = self
+            .threads
+            .keys()
+            .copied()
+            .filter(|&t| {
+                t != tid
+                    && !self.group_stopped.contains(&t)
+                    && !self.queued.iter().any(|&(q, _)| q == t)
+            })
+            .filter(|&t| ptrace::interrupt(t).is_ok())
+            .collect();
+        let mut held = Vec::new();
+        for t in others {
+            let Ok(status) = wait_thread(t) else { continue };
+            if libc::WIFSTOPPED(status)
+                && libc::WSTOPSIG(status) == libc::SIGTRAP
+                && status >> 16 == libc::PTRACE_EVENT_STOP
+            {
+                held.push(t);
+            } else {
+                self.queued.push_back((t, status));
+            }
+        }
+        held
+    }
+
+    /// Plants a breakpoint at `addr` through the stopped thread `tid`, or
+    /// counts one more call returning to an existing one.
+    fn insert(&mut self, tid: pid_t, addr: u64, entry: Option<CallKind>) -> io::Result<()> {
+        if let Some(bp) = self.breakpoints.get_mut(&addr) {
+            bp.entry = bp.entry.or(entry);
+            bp.returns += entry.is_none() as usize;
+            return Ok(());
+        }
+        let orig = ptrace::poke_byte(tid, addr, INT3)?;
+        self.removed.remove(&addr);
+        self.breakpoints.insert(
+            addr,
+            Breakpoint {
+                orig,
+                entry,
+                returns: entry.is_none() as usize,
+            },
+        );
+        Ok(())
+    }
+
+    /// Drops one call returning to `addr`, removing the breakpoint with the
+    /// last one unless it is also an entry point.
+    fn release_return(&mut self, tid: pid_t, addr: u64) -> io::Result<()> {
+        let bp = self.breakpoints.get_mut(&addr).unwrap();
+        bp.returns = bp.returns.saturating_sub(1);
+        if bp.returns == 0 && bp.entry.is_none() {
+            let orig = bp.orig;
+            self.breakpoints.remove(&addr);
+            self.removed.insert(addr);
+            ptrace::poke_byte(tid, addr, orig)?;
+        }
+        Ok(())
+    }
+
+    fn args(&self, tid: pid_t, regs: &user_regs_struct, count: usize) -> io::Result<Vec<u64>> {
+        match self.arch {
+            Arch::X64 => Ok([regs.rdi, regs.rsi, regs.rdx][..count].to_vec()),
+            // cdecl: the arguments follow the return address on the stack.
+            Arch::X86 => (1..=count as u64)
+                .map(|i| self.read_word(tid, regs.rsp + 4 * i))
+                .collect(),
+        }
+    }
+
+    fn read_word(&self, tid: pid_t, addr: u64) -> io::Result<u64> {
+        Ok(self.word(ptrace::peek(tid, addr)?))
+    }
+
+    fn ptr_size(&self) -> u64 {
+        match self.arch {
+            Arch::X64 => 8,
+            Arch::X86 => 4,
+        }
+    }
+
+    /// A register or memory word cut to the target's pointer size.
+    fn word(&self, value: u64) -> u64 {
+        match self.arch {
+            Arch::X64 => value,
+            Arch::X86 => value & 0xffff_ffff,
+        }
+    }
+
+    fn locate(&mut self, addr: u64) -> Option<(String, u64)> {
+        if let Some(site) = self.maps.locate(addr) {
+            return Some(site);
+        }
+        // Code mapped since the last look, e.g. a dlopen()ed library.
+        self.maps = Proc::new(self.pid as u64).maps();
+        self.maps.locate(addr)
+    }
+
+    /// Seizes and stops every thread, following new threads until a pass
+    /// over `/proc/<pid>/task` finds none. Returns the signal each thread
+    /// stopped for, to deliver when it is resumed.
+    fn seize_all(&mut self) -> io::Result<Vec<(pid_t, i32)>> {
+        let mut seized = Vec::new();
+        loop {
+            let new: Vec<pid_t> = Proc::new(self.pid as u64)
+                .tasks()
+                .into_iter()
+                .map(|tid| tid as pid_t)
+                .filter(|tid| !self.threads.contains_key(tid))
+                .collect();
+            if new.is_empty() {
+                return Ok(seized);
+            }
+            for tid in new {
+                let sig = match ptrace::seize_stop(tid, libc::PTRACE_O_TRACECLONE) {
+                    Ok(sig) => sig,
+                    Err(e) if e.raw_os_error() == Some(libc::ESRCH) => continue,
+                    Err(e) => {
+                        self.detach_all(&seized);
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("Failed to attach to thread {}: {}", tid, e),
+                        ));
+                    }
+                };
+                self.threads.insert(tid, Vec::new());
+                seized.push((tid, sig));
+            }
+        }
+    }
+
+    /// Stops every thread, winds back the ones sitting just past one of our
+    /// `int3`s, removes the breakpoints and detaches.
+    fn shutdown(&mut self) {
+        let mut queued: HashMap<pid_t, i32> = self.queued.drain(..).collect();
+        let tids: Vec<pid_t> = self.threads.keys().copied().collect();
+        for &tid in tids.iter().filter(|t| !queued.contains_key(t)) {
+            let _ = ptrace::interrupt(tid);
+        }
+        let mut stopped = Vec::new();
+        for tid in tids {
+            let status = match queued.remove(&tid) {
+                Some(status) => status,
+                None => match wait_thread(tid) {
+                    Ok(status) => status,
+                    Err(_) => continue,
+                },
+            };
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+            let sig = match (libc::WSTOPSIG(status), status >> 16) {
+                (libc::SIGTRAP, 0) => {
+                    if let Ok(mut regs) = ptrace::getregs(tid)
+                        && self.breakpoints.contains_key(&regs.rip.wrapping_sub(1))
+                    {
+                        regs.rip -= 1;
+                        let _ = ptrace::setregs(tid, &regs);
+                    }
+                    0
+                }
+                (_, libc::PTRACE_EVENT_STOP) | (libc::SIGTRAP, _) => 0,
+                (sig, _) => sig,
+            };
+            stopped.push((tid, sig));
+        }
+        self.detach_all(&stopped);
+    }
+
+    /// Restores every patched byte and detaches the stopped `threads`,
+    /// delivering their signals.
+    fn detach_all(&mut self, threads: &[(pid_t, i32)]) {
+        if let Some(&(tid, _)) = threads.first() {
+            for (&addr, bp) in &self.breakpoints {
+                let _ = ptrace::poke_byte(tid, addr, bp.orig);
+            }
+        }
+        self.breakpoints.clear();
+        for &(tid, sig) in threads {
+            let _ = ptrace::detach_signal(tid, sig);
+        }
+        self.threads.clear();
+    }
+}
+
+/// Waits for `tid` to change state and returns the raw status.
+fn wait_thread(tid: pid_t) -> io::Result<i32> {
+    loop {
+        match ptrace::wait_raw(tid) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            other => return other.map(|(_, status)| status),
+        }
+    }
+}
+
+fn set_handlers(handler: libc::sighandler_t) {
+    for sig in STOP_SIGNALS {
+        unsafe {
+            // No SA_RESTART, so a blocking waitpid returns EINTR.
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler;
+            libc::sigaction(sig, &action, std::ptr::null_mut());
+        }
+    }
+}
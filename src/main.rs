@@ -1,76 +1,703 @@
 mod internals;
-use internals::core::{Chain, HeapInspector, HeapInspectorConfig};
+use internals::calibrate::calibrate;
+use internals::check::{Violation, check};
+use internals::common::{Arch, parse_u64};
+use internals::core::{
+    ArenaRecord, Chain, ChunkClass, ChunkIssue, FASTCHUNKS_BIT, HeapInspector, HeapInspectorConfig,
+    Link, MallocChunk, MallocPar, MallocState, NONCONTIGUOUS_BIT, NextsizeRing, Snapshot, Tcache,
+    ThreadRecord,
+};
+use internals::diff::{ChunkChange, ChunkView, HeapDiff, diff};
+use internals::freeze::FreezeMethod;
+use internals::profile;
+use internals::snapfile;
+use internals::trace::{HeapCall, Tracer};
 use libc::geteuid;
+use std::collections::HashMap;
 use std::env;
-
-fn parse_u64(input: &str) -> Option<u64> {
-    if let Some(rest) = input.strip_prefix("0x") {
-        u64::from_str_radix(rest, 16).ok()
-    } else {
-        input.parse::<u64>().ok()
-    }
-}
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
+        let prog = args.first().map(String::as_str).unwrap_or("heapfinder");
         eprintln!(
-            "Usage: {} <pid>",
-            args.get(0).map(String::as_str).unwrap_or("heapfinder")
+            "Usage: {} <pid> [--debug-dir <dir>] [--freeze <ptrace|sigstop>] [--save <file>]",
+            prog
         );
+        eprintln!("       {} core <file> [--libc <path>] [--debug-dir <dir>] [--save <file>]", prog);
+        eprintln!("       {} load <snapshot>", prog);
+        eprintln!("       {} diff <before> <after>   (snapshot files or pids)", prog);
+        eprintln!("       {} check <pid|snapshot>", prog);
+        eprintln!(
+            "       {} watch <pid> [--interval <ms>] [--freeze <ptrace|sigstop>]",
+            prog
+        );
+        eprintln!("       {} trace <pid> [--output <file>]", prog);
+        eprintln!("       {} calibrate --libc <path> [--ld <path>]", prog);
+        eprintln!("       {} profile add <json> [--libc <path>]", prog);
+        eprintln!("       {} profile list", prog);
+        return;
+    }
+    if args[1] == "calibrate" {
+        run_calibrate(&args[2..]);
+        return;
+    }
+    if args[1] == "profile" {
+        run_profile(&args[2..]);
+        return;
+    }
+    if args[1] == "core" {
+        run_core(&args[2..]);
+        return;
+    }
+    if args[1] == "load" {
+        run_load(&args[2..]);
+        return;
+    }
+    if args[1] == "diff" {
+        run_diff(&args[2..]);
+        return;
+    }
+    if args[1] == "check" {
+        run_check(&args[2..]);
+        return;
+    }
+    if args[1] == "watch" {
+        run_watch(&args[2..]);
+        return;
+    }
+    if args[1] == "trace" {
+        run_trace(&args[2..]);
         return;
     }
 
-    let pid = match parse_u64(&args[1]) {
+    let Some(hi) = attach(&args[1], &args[2..]) else {
+        return;
+    };
+    match hi.capture() {
+        Ok(snap) => report_and_save(&snap, option_value(&args[2..], "--save")),
+        Err(e) => eprintln!("Failed to capture process {}: {}", hi.pid(), e),
+    }
+}
+
+/// A `HeapInspector` on the live process `pid`, configured from the
+/// `--debug-dir` and `--freeze` options. Errors are reported here.
+fn attach(pid: &str, args: &[String]) -> Option<HeapInspector> {
+    let pid = match parse_u64(pid) {
         Some(v) => v,
         None => {
-            eprintln!("Invalid pid: {}", args[1]);
+            eprintln!("Invalid pid: {}", pid);
+            return None;
+        }
+    };
+    if unsafe { geteuid() } != 0 {
+        eprintln!("Root privileges required. Please run with sudo.");
+        return None;
+    }
+    let freeze = match option_value(args, "--freeze") {
+        Some(name) => match FreezeMethod::parse(name) {
+            Some(m) => Some(m),
+            None => {
+                eprintln!("Unknown freeze method: {} (ptrace or sigstop)", name);
+                return None;
+            }
+        },
+        None => None,
+    };
+    let config = HeapInspectorConfig {
+        debug_dir: option_value(args, "--debug-dir").map(PathBuf::from),
+        freeze,
+        ..Default::default()
+    };
+    match HeapInspector::new(pid, config) {
+        Ok(hi) => Some(hi),
+        Err(e) => {
+            eprintln!("Failed to initialize HeapInspector: {}", e);
+            None
+        }
+    }
+}
+
+/// Prints the full report once, then what changed at every `--interval`
+/// until the process exits.
+fn run_watch(args: &[String]) {
+    let Some(pid) = args.first() else {
+        eprintln!("Usage: heapfinder watch <pid> [--interval <ms>] [--freeze <ptrace|sigstop>]");
+        return;
+    };
+    let interval = match option_value(args, "--interval") {
+        Some(ms) => match parse_u64(ms) {
+            Some(ms) if ms > 0 => Duration::from_millis(ms),
+            _ => {
+                eprintln!("Invalid interval: {}", ms);
+                return;
+            }
+        },
+        None => Duration::from_secs(1),
+    };
+    let Some(hi) = attach(pid, &args[1..]) else {
+        return;
+    };
+    let mut last = match hi.capture() {
+        Ok(snap) => {
+            print_report(&snap);
+            snap.record()
+        }
+        Err(e) => {
+            eprintln!("Failed to capture process {}: {}", hi.pid(), e);
             return;
         }
     };
+    let start = Instant::now();
+    loop {
+        thread::sleep(interval);
+        let snap = hi.capture();
+        // A capture racing with exit sees a torn or empty heap.
+        if !hi.is_alive() {
+            println!("\nprocess {} exited", hi.pid());
+            return;
+        }
+        let record = match snap {
+            Ok(snap) => snap.record(),
+            Err(e) => {
+                eprintln!("Failed to capture process {}: {}", hi.pid(), e);
+                continue;
+            }
+        };
+        let changes = diff(&last, &record);
+        if !changes.is_empty() {
+            println!("\n[+{:.3}s]", start.elapsed().as_secs_f64());
+            print_diff(&changes);
+        }
+        last = record;
+    }
+}
+
+/// Logs every malloc/free/calloc/realloc/memalign call until the process
+/// exits or heapfinder is interrupted.
+fn run_trace(args: &[String]) {
+    let Some(pid) = args.first() else {
+        eprintln!("Usage: heapfinder trace <pid> [--output <file>]");
+        return;
+    };
+    let Some(pid) = parse_u64(pid) else {
+        eprintln!("Invalid pid: {}", pid);
+        return;
+    };
     if unsafe { geteuid() } != 0 {
         eprintln!("Root privileges required. Please run with sudo.");
         return;
     }
-    let main_arena_offset = 0x210ac0;
-    let tcache_enable = true;
+    let mut out: Box<dyn Write> = match option_value(args, "--output") {
+        Some(path) => match File::create(path) {
+            Ok(f) => Box::new(BufWriter::new(f)),
+            Err(e) => {
+                eprintln!("Failed to create {}: {}", path, e);
+                return;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let tracer = match Tracer::attach(pid) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to trace process {}: {}", pid, e);
+            return;
+        }
+    };
+    let size_t = match tracer.arch() {
+        Arch::X64 => 8,
+        Arch::X86 => 4,
+    };
+    eprintln!("tracing process {}, Ctrl-C to stop", pid);
+    let mut count = 0;
+    let result = tracer.run(|call| {
+        count += 1;
+        let _ = writeln!(out, "{}", format_call(call, size_t));
+    });
+    let _ = out.flush();
+    match result {
+        Ok(()) => eprintln!("{} calls traced", count),
+        Err(e) => eprintln!("Tracing stopped after {} calls: {}", count, e),
+    }
+}
 
+/// `+secs [tid] name(args) = ret chunk <addr> from <caller>`, where the
+/// chunk is the one the call returned or released.
+fn format_call(call: &HeapCall, size_t: u64) -> String {
+    let args: Vec<String> = call.args.iter().map(|a| format!("0x{:x}", a)).collect();
+    let mut line = format!(
+        "+{:.6} [{}] {}({})",
+        call.time.as_secs_f64(),
+        call.tid,
+        call.kind,
+        args.join(", ")
+    );
+    if let Some(ret) = call.ret {
+        line += &format!(" = 0x{:x}", ret);
+    }
+    if let Some(mem) = call.allocated().or(call.freed()) {
+        line += &format!(" chunk 0x{:x}", mem - 2 * size_t);
+    }
+    match &call.caller_site {
+        Some((name, off)) => {
+            let file = Path::new(name).file_name().map_or(name.clone(), |f| f.to_string_lossy().to_string());
+            line += &format!(" from {}+0x{:x}", file, off);
+        }
+        None => line += &format!(" from 0x{:x}", call.caller),
+    }
+    line
+}
+
+fn run_core(args: &[String]) {
+    let path = match args.first() {
+        Some(p) => p,
+        None => {
+            eprintln!(
+                "Usage: heapfinder core <file> [--libc <path>] [--debug-dir <dir>] [--save <file>]"
+            );
+            return;
+        }
+    };
     let config = HeapInspectorConfig {
-        main_arena_offset,
-        tcache_enable,
-        libc_version: None,
+        debug_dir: option_value(args, "--debug-dir").map(PathBuf::from),
+        libc_path: option_value(args, "--libc").map(str::to_string),
+        ..Default::default()
     };
+    match HeapInspector::from_core(path, config).and_then(|hi| hi.capture()) {
+        Ok(snap) => report_and_save(&snap, option_value(args, "--save")),
+        Err(e) => eprintln!("Failed to initialize HeapInspector: {}", e),
+    }
+}
 
-    let hi = match HeapInspector::new(pid, config) {
-        Ok(v) => v,
+fn run_load(args: &[String]) {
+    let path = match args.first() {
+        Some(p) => Path::new(p),
+        None => {
+            eprintln!("Usage: heapfinder load <snapshot>");
+            return;
+        }
+    };
+    match snapfile::load(path) {
+        Ok(snap) => print_report(&snap),
+        Err(e) => eprintln!("Failed to load snapshot: {}", e),
+    }
+}
+
+fn run_diff(args: &[String]) {
+    if args.len() < 2 {
+        eprintln!("Usage: heapfinder diff <before> <after>   (snapshot files or pids)");
+        return;
+    }
+    let before = match open_snapshot(&args[0]) {
+        Ok(s) => s,
         Err(e) => {
-            eprintln!("Failed to initialize HeapInspector: {}", e);
+            eprintln!("Failed to read {}: {}", args[0], e);
+            return;
+        }
+    };
+    let after = match open_snapshot(&args[1]) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", args[1], e);
             return;
         }
     };
+    print_diff(&diff(&before.record(), &after.record()));
+}
 
-    let record = hi.record();
+fn run_check(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("Usage: heapfinder check <pid|snapshot>");
+        return;
+    }
+    let snap = match open_snapshot(&args[0]) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", args[0], e);
+            return;
+        }
+    };
+    let violations = check(&snap);
+    if violations.is_empty() {
+        println!("no violations");
+        return;
+    }
+    for violation in &violations {
+        println!("{}", format_violation(violation));
+    }
+    println!("{} violations", violations.len());
+}
+
+/// `0x<chunk> <check>: <glibc message> (<detail>)`
+fn format_violation(violation: &Violation) -> String {
+    match violation.message {
+        Some(message) => format!(
+            "0x{:x} {}: {} ({})",
+            violation.addr,
+            violation.check.name(),
+            message,
+            violation.detail
+        ),
+        None => format!(
+            "0x{:x} {}: {}",
+            violation.addr,
+            violation.check.name(),
+            violation.detail
+        ),
+    }
+}
+
+/// A snapshot file, or a fresh capture when `arg` is a pid. Capturing
+/// needs root, like every other command that reads a live process.
+fn open_snapshot(arg: &str) -> io::Result<Snapshot> {
+    match parse_u64(arg) {
+        Some(pid) if !Path::new(arg).exists() => {
+            if unsafe { geteuid() } != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Root privileges required. Please run with sudo.",
+                ));
+            }
+            HeapInspector::new(pid, HeapInspectorConfig::default())?.capture()
+        }
+        _ => snapfile::load(Path::new(arg)),
+    }
+}
+
+fn print_diff(diff: &HeapDiff) {
+    if diff.is_empty() {
+        println!("no changes");
+        return;
+    }
+    for change in &diff.fields {
+        println!(
+            "arena 0x{:x} {}: 0x{:x} -> 0x{:x}",
+            change.arena, change.field, change.before, change.after
+        );
+    }
+    for change in &diff.chunks {
+        let label = change.label();
+        match change {
+            ChunkChange::Created(c) | ChunkChange::Removed(c) => {
+                println!("{} {}", label, format_view(c));
+            }
+            ChunkChange::Rebinned { before, after } => {
+                println!(
+                    "{} 0x{:x} size 0x{:x}: {} -> {}",
+                    label,
+                    before.addr,
+                    before.size,
+                    format_state(before),
+                    format_state(after)
+                );
+            }
+            ChunkChange::Split { before, after } => {
+                println!("{} {}", label, format_view(before));
+                after.iter().for_each(|c| println!("  -> {}", format_view(c)));
+            }
+            ChunkChange::Merged { before, after } => {
+                println!("{} {}", label, format_view(after));
+                before.iter().for_each(|c| println!("  <- {}", format_view(c)));
+            }
+            ChunkChange::Reshaped { before, after } => {
+                println!("{}", label);
+                before.iter().for_each(|c| println!("  before {}", format_view(c)));
+                after.iter().for_each(|c| println!("  after  {}", format_view(c)));
+            }
+        }
+    }
+}
+
+fn format_view(chunk: &ChunkView) -> String {
+    format!("0x{:x} size 0x{:x} {}", chunk.addr, chunk.size, format_state(chunk))
+}
+
+/// `top`, `in-use`, or the free lists holding the chunk.
+fn format_state(chunk: &ChunkView) -> String {
+    if chunk.top {
+        return "top".to_string();
+    }
+    if chunk.bins.is_empty() {
+        return "in-use".to_string();
+    }
+    chunk.bins.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// `0x<addr> size 0x<size> <state>`, followed by any issues.
+fn format_class(addr: u64, classes: &HashMap<u64, &ChunkClass>) -> String {
+    let Some(class) = classes.get(&addr) else {
+        return format!("0x{:x}", addr);
+    };
+    let mut out = format!("0x{:x} size 0x{:x} {}", class.addr, class.size, class.state);
+    for issue in &class.issues {
+        out.push_str(&format!(" !{}", issue));
+    }
+    out
+}
+
+fn report_and_save(snap: &Snapshot, save: Option<&str>) {
+    print_report(snap);
+    if let Some(path) = save {
+        match snapfile::save(snap, Path::new(path)) {
+            Ok(()) => eprintln!("snapshot saved to {}", path),
+            Err(e) => eprintln!("Failed to save snapshot: {}", e),
+        }
+    }
+}
+
+fn print_report(snap: &Snapshot) {
+    let info = snap.info();
+    for warning in &info.warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let record = snap.record();
+    let record_classes = record.chunk_classes();
 
     println!("pid: {}", record.pid);
     println!("arch: {:?}", record.arch);
+    println!("libc_version: {}", record.libc_version);
     println!("libc_base: 0x{:x}", record.libc_base);
+    println!("main_arena_offset: 0x{:x}", info.main_arena_offset);
+    if let Some(path) = &info.symbols.debug_file {
+        println!("debug_file: {}", path.display());
+    }
     println!("heap_base: 0x{:x}", record.heap_base);
+    println!("tcache_enable: {}", record.tcache_enable);
+    if let Some(mp) = &record.malloc_par {
+        print_malloc_par(mp);
+    }
+    if let Some(state) = &record.main_arena {
+        println!("main_arena: 0x{:x}", state.addr());
+        print_state(snap, state);
+    }
     println!("heap_chunks: {}", record.heap_chunks.len());
     println!("fastbins: {}", record.fastbins.len());
+    let classes: HashMap<u64, &ChunkClass> = record_classes.iter().map(|c| (c.addr, c)).collect();
     for (i, chunk) in record.heap_chunks.iter().enumerate() {
-        println!("heap_chunk[{}]: 0x{:x}", i, chunk.addr());
+        println!("heap_chunk[{}]: {}", i, format_class(chunk.addr(), &classes));
     }
 
-    print_chain_map("tcache", &hi.tcache_chains());
-    if let Some(chain) = hi.unsortedbin_chain() {
+    println!("mmapped_chunks: {}", record.mmapped_chunks.len());
+    for (i, chunk) in record.mmapped_chunks.iter().enumerate() {
+        println!(
+            "mmapped_chunk[{}]: 0x{:x} size 0x{:x} flags {} mem 0x{:x} usable 0x{:x}",
+            i,
+            chunk.addr(),
+            chunk.size(),
+            format_flags(chunk),
+            chunk.mem(),
+            chunk.usable_size()
+        );
+    }
+    // Free-listed chunks outside every heap, e.g. fake chunks.
+    let outside = record_classes
+        .iter()
+        .filter(|c| c.issues.contains(&ChunkIssue::OutsideHeap));
+    for (i, class) in outside.enumerate() {
+        println!("listed_chunk[{}]: {}", i, format_class(class.addr, &classes));
+    }
+
+    if let Some(tcache) = &record.tcache {
+        print_tcache("tcache", tcache, &snap.tcache_chains());
+    }
+    if let Some(chain) = snap.unsortedbin_chain() {
         print_chain("unsortedbin", &chain);
     }
-    print_chain_map("fastbin", &hi.fastbin_chains());
-    print_chain_map("smallbin", &hi.smallbin_chains());
-    print_chain_map("largebin", &hi.largebin_chains());
+    print_chain_map("fastbin", &snap.fastbin_chains());
+    print_chain_map("smallbin", &snap.smallbin_chains());
+    print_chain_map("largebin", &snap.largebin_chains());
+    print_rings("largebin", &snap.largebin_rings());
+
+    for thread in &record.threads {
+        print_thread(snap, thread);
+    }
+    for (i, arena) in record.arenas.iter().enumerate() {
+        print_arena(snap, i + 1, arena, &classes);
+    }
+}
+
+fn print_thread(snap: &Snapshot, thread: &ThreadRecord) {
+    let label = format!("thread[{}]", thread.tid);
+    let tcache = thread.tcache.as_ref().map_or(0, |t| t.addr());
+    println!(
+        "{}: fs_base 0x{:x}, arena 0x{:x}, tcache 0x{:x}",
+        label, thread.fs_base, thread.arena, tcache
+    );
+    if let Some(tcache) = &thread.tcache {
+        print_tcache(&format!("{}.tcache", label), tcache, &snap.thread_tcache_chains(tcache));
+    }
 }
 
-fn print_chain_map(label: &str, chains: &std::collections::HashMap<usize, Chain>) {
+fn print_arena(
+    snap: &Snapshot,
+    index: usize,
+    arena: &ArenaRecord,
+    classes: &HashMap<u64, &ChunkClass>,
+) {
+    let label = format!("arena[{}]", index);
+    println!("{}: 0x{:x}", label, arena.addr);
+    print_state(snap, &arena.state);
+    for heap in &arena.heaps {
+        println!("  heap: 0x{:x}-0x{:x}", heap.start, heap.end);
+    }
+    println!("  heap_chunks: {}", arena.heap_chunks.len());
+    for (i, chunk) in arena.heap_chunks.iter().enumerate() {
+        println!("  heap_chunk[{}]: {}", i, format_class(chunk.addr(), classes));
+    }
+    let state = &arena.state;
+    if let Some(chain) = snap.arena_unsortedbin_chain(state) {
+        print_chain(&format!("{}.unsortedbin", label), &chain);
+    }
+    print_chain_map(&format!("{}.fastbin", label), &snap.arena_fastbin_chains(state));
+    print_chain_map(&format!("{}.smallbin", label), &snap.arena_smallbin_chains(state));
+    print_chain_map(&format!("{}.largebin", label), &snap.arena_largebin_chains(state));
+    print_rings(&format!("{}.largebin", label), &snap.arena_largebin_rings(state));
+}
+
+fn run_calibrate(args: &[String]) {
+    let libc_path = option_value(args, "--libc");
+    let ld_path = option_value(args, "--ld");
+    let libc_path = match libc_path {
+        Some(p) => p,
+        None => {
+            eprintln!("calibrate: --libc <path> is required");
+            return;
+        }
+    };
+
+    let calibration = match calibrate(libc_path, ld_path) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Failed to calibrate {}: {}", libc_path, e);
+            return;
+        }
+    };
+    print!("{}", profile::profile_json(&calibration));
+    let key = match profile::libc_key(libc_path) {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", libc_path, e);
+            return;
+        }
+    };
+    match profile::store(&key, &calibration) {
+        Ok(path) => eprintln!("saved to {}", path.display()),
+        Err(e) => eprintln!("Failed to save profile: {}", e),
+    }
+}
+
+fn run_profile(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("list") => {
+            for (key, p) in profile::list() {
+                println!(
+                    "{}: glibc {} main_arena=0x{:x} tcache={}",
+                    key, p.libc_version, p.main_arena_offset, p.tcache_enable
+                );
+            }
+        }
+        Some("add") if args.len() >= 2 => {
+            let text = match std::fs::read_to_string(&args[1]) {
+                Ok(t) => t,
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", args[1], e);
+                    return;
+                }
+            };
+            let mut entry = match profile::parse_profile(&text) {
+                Some(p) => p,
+                None => {
+                    eprintln!("{}: needs libc_version, main_arena_offset and tcache_enable", args[1]);
+                    return;
+                }
+            };
+            // libc_info.c output carries no build-id, so take it from the libc.
+            if let Some(libc_path) = option_value(args, "--libc") {
+                match profile::libc_key(libc_path) {
+                    Ok(k) => entry.build_id = Some(k),
+                    Err(e) => {
+                        eprintln!("Failed to read {}: {}", libc_path, e);
+                        return;
+                    }
+                }
+            }
+            let key = match &entry.build_id {
+                Some(k) => k.clone(),
+                None => {
+                    eprintln!("{}: no build_id, pass --libc <path>", args[1]);
+                    return;
+                }
+            };
+            match profile::store(&key, &entry) {
+                Ok(path) => println!("saved to {}", path.display()),
+                Err(e) => eprintln!("Failed to save profile: {}", e),
+            }
+        }
+        _ => eprintln!("Usage: profile add <json> [--libc <path>] | profile list"),
+    }
+}
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let pos = args.iter().position(|a| a == name)?;
+    args.get(pos + 1).map(String::as_str)
+}
+
+fn print_malloc_par(mp: &MallocPar) {
+    let hex = |v: Option<u64>| v.map_or("-".to_string(), |v| format!("0x{:x}", v));
+    let dec = |v: Option<u64>| v.map_or("-".to_string(), |v| v.to_string());
+    println!("mp_: 0x{:x}", mp.addr());
+    println!("  trim_threshold: {}", hex(mp.trim_threshold()));
+    println!("  mmap_threshold: {}", hex(mp.mmap_threshold()));
+    println!("  arena_max: {}", dec(mp.arena_max()));
+    println!("  n_mmaps: {}", dec(mp.n_mmaps().map(u64::from)));
+    println!("  max_n_mmaps: {}", dec(mp.max_n_mmaps().map(u64::from)));
+    println!("  sbrk_base: {}", hex(mp.sbrk_base()));
+    println!("  tcache_bins: {}", dec(mp.tcache_bins()));
+    println!("  tcache_count: {}", dec(mp.tcache_count()));
+    println!("  tcache_unsorted_limit: {}", dec(mp.tcache_unsorted_limit()));
+}
+
+/// The `malloc_state` fields besides the bins, then any issue with `top`
+/// or `binmap`.
+fn print_state(snap: &Snapshot, state: &MallocState) {
+    let hex = |v: Option<u64>| v.map_or("-".to_string(), |v| format!("0x{:x}", v));
+    let top_size = snap.chunk_at(state.top()).map(|c| c.size());
+    println!("  top: 0x{:x} size {}", state.top(), hex(top_size));
+    println!("  last_remainder: 0x{:x}", state.last_remainder());
+    if let Some(flags) = state.flags() {
+        let names: Vec<&str> = [(FASTCHUNKS_BIT, "FASTCHUNKS_BIT"), (NONCONTIGUOUS_BIT, "NONCONTIGUOUS_BIT")]
+            .iter()
+            .filter(|&&(bit, _)| flags & bit != 0)
+            .map(|&(_, name)| name)
+            .collect();
+        println!("  flags: 0x{:x} [{}]", flags, names.join(" "));
+    }
+    if let Some(have) = state.have_fastchunks() {
+        println!("  have_fastchunks: {}", have);
+    }
+    if let Some(binmap) = state.binmap() {
+        let words: Vec<String> = binmap.iter().map(|w| format!("0x{:08x}", w)).collect();
+        let marked: Vec<String> = (0..binmap.len() * 32)
+            .filter(|&i| state.bin_marked(i) == Some(true))
+            .map(|i| i.to_string())
+            .collect();
+        println!("  binmap: {} (bins {})", words.join(" "), marked.join(" "));
+    }
+    println!("  system_mem: {}", hex(state.system_mem()));
+    println!("  max_system_mem: {}", hex(state.max_system_mem()));
+    for issue in snap.arena_issues(state) {
+        println!("  !{}", issue);
+    }
+}
+
+fn print_chain_map(label: &str, chains: &HashMap<usize, Chain>) {
     let mut keys: Vec<usize> = chains.keys().copied().collect();
     keys.sort_unstable();
     for key in keys {
@@ -80,6 +707,26 @@ fn print_chain_map(label: &str, chains: &std::collections::HashMap<usize, Chain>
     }
 }
 
+/// Every non-empty tcache bin as its stored count and the chain walked from
+/// its head. glibc trusts the count, so a bin whose count and chain length
+/// differ is flagged.
+fn print_tcache(label: &str, tcache: &Tcache, chains: &HashMap<usize, Chain>) {
+    let counts = tcache.counts();
+    let mut keys: Vec<usize> = chains.keys().copied().collect();
+    keys.extend((0..counts.len()).filter(|&i| counts[i] != 0));
+    keys.sort_unstable();
+    keys.dedup();
+    for key in keys {
+        let count = counts.get(key).copied().unwrap_or(0);
+        let walked = chains.get(&key).map_or(0, |c| c.addrs.len());
+        let mismatch = if usize::from(count) == walked { "" } else { " (mismatch)" };
+        println!("{}[{}].count: {}, walked {}{}", label, key, count, walked, mismatch);
+        if let Some(chain) = chains.get(&key) {
+            print_chain(&format!("{}[{}]", label, key), chain);
+        }
+    }
+}
+
 fn print_chain(label: &str, chain: &Chain) {
     if chain.addrs.is_empty() {
         return;
@@ -90,9 +737,58 @@ fn print_chain(label: &str, chain: &Chain) {
             out.push_str(" -> ");
         }
         out.push_str(&format!("0x{:x}", addr));
+        if let Some(link) = chain.links.get(i) {
+            out.push_str(&format_link(link));
+        }
     }
     if chain.cycle {
         out.push_str(" -> (cycle)");
     }
     println!("{}: {}", label, out);
 }
+
+/// Size groups of every large bin in nextsize order, each as
+/// `size [chunks]`, then the issues found on the ring.
+fn print_rings(label: &str, rings: &HashMap<usize, NextsizeRing>) {
+    let mut keys: Vec<usize> = rings.keys().copied().collect();
+    keys.sort_unstable();
+    for key in keys {
+        let ring = &rings[&key];
+        let groups: Vec<String> = ring
+            .groups
+            .iter()
+            .map(|group| {
+                let chunks: Vec<String> =
+                    group.chunks.iter().map(|c| format!("0x{:x}", c.addr())).collect();
+                format!("0x{:x} [{}]", group.size, chunks.join(", "))
+            })
+            .collect();
+        let end = if ring.closed { "" } else { " -> (open)" };
+        println!("{}[{}].nextsize: {}{}", label, key, groups.join(" -> "), end);
+        for issue in &ring.issues {
+            println!("{}[{}].nextsize: !{}", label, key, issue);
+        }
+    }
+}
+
+/// PREV_INUSE, IS_MMAPPED and NON_MAIN_ARENA as `P`, `M` and `A`.
+fn format_flags(chunk: &MallocChunk) -> String {
+    [(chunk.prev_inuse(), 'P'), (chunk.is_mmapped(), 'M'), (chunk.non_main_arena(), 'A')]
+        .iter()
+        .map(|&(set, c)| if set { c } else { '-' })
+        .collect()
+}
+
+fn format_link(link: &Link) -> String {
+    let mut out = String::new();
+    if link.raw != link.decoded {
+        out.push_str(&format!(" [0x{:x} => 0x{:x}]", link.raw, link.decoded));
+    }
+    if link.misaligned {
+        out.push_str(" (misaligned)");
+    }
+    if link.unmapped {
+        out.push_str(" (unmapped)");
+    }
+    out
+}
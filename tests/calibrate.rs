@@ -0,0 +1,39 @@
+use std::path::Path;
+use std::process::Command;
+
+/// `main_arena` of `tests/libc.so.6` (glibc 2.41), as printed by
+/// `libc/libc_info64` run under it.
+const MAIN_ARENA_OFFSET: &str = "0x210ac0";
+
+#[test]
+#[ignore = "runs the bundled libc under ptrace, needs root"]
+fn calibrate_bundled_libc() {
+    let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let profiles =
+        std::env::temp_dir().join(format!("heapfinder-calibrate-{}", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_heapfinder"))
+        .arg("calibrate")
+        .arg("--libc")
+        .arg(tests.join("libc.so.6"))
+        .arg("--ld")
+        .arg(tests.join("ld-linux-x86-64.so.2"))
+        .env("HEAPFINDER_PROFILES", &profiles)
+        .output()
+        .expect("failed to run heapfinder");
+    let _ = std::fs::remove_dir_all(&profiles);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "calibrate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains(&format!("\"main_arena_offset\": \"{MAIN_ARENA_OFFSET}\"")),
+        "unexpected profile:\n{stdout}"
+    );
+    assert!(
+        stdout.contains("\"tcache_enable\": true"),
+        "unexpected profile:\n{stdout}"
+    );
+}